@@ -0,0 +1,238 @@
+//! ### Shared Archive
+//!
+//! A resource holding a value archived with rkyv, which readers use in place over the
+//! shared memory segment, without deserializing it. Requires the `rkyv` feature.
+//!
+
+use rkyv::Archived;
+
+use crate::error::Error;
+use crate::options::{LockKind, LockTimeout, OpenMode, ResourceOptions};
+use crate::outcome::CloseOutcome;
+use crate::unix::archive::{Archivable, UnixSharedArchive};
+
+/// A value archived with rkyv, shared across processes.
+///
+/// `T` must be the same type in every process. Processes attach to, lock and close a
+/// `SharedArchive` the same way as a `SharedResource`.
+///
+pub enum SharedArchive<T: Archivable> {
+    Unix(UnixSharedArchive<T>),
+}
+
+impl<T: Archivable> SharedArchive<T> {
+    /// Open the shared value with the given name, creating it with `initial_value` if it
+    /// does not exist yet.
+    ///
+    pub fn new(name: &str, initial_value: T) -> Result<SharedArchive<T>, Error> {
+        return Self::open(
+            name,
+            OpenMode::CreateOrOpen(initial_value),
+            LockKind::default(),
+        );
+    }
+
+    /// Open the shared value with the given name, creating it with the value built by
+    /// `init` if it does not exist yet. `init` only runs in the process that creates it.
+    ///
+    pub fn open_or_init<F: FnOnce() -> T>(name: &str, init: F) -> Result<SharedArchive<T>, Error> {
+        return Self::open_with_options(
+            name,
+            OpenMode::CreateOrOpen(init),
+            &ResourceOptions::default(),
+        );
+    }
+
+    /// Open the shared value with the given name.
+    ///
+    /// #### Arguments
+    /// - `name`: name of the value
+    /// - `mode`: whether to create the value, open it, or both, with the value if created
+    /// - `lock`: kind of lock guarding the value, the same in every process
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedArchive`. If the mode forbids creating or opening the
+    /// value, returns `Error::AlreadyExists` or `Error::NotFound`. If the value exists with
+    /// another type, returns `Error::TypeMismatch`. If it exists with another kind of lock,
+    /// returns `Error::IncompatibleSegment`. On failure, returns an
+    /// `Error`.
+    ///
+    pub fn open(name: &str, mode: OpenMode<T>, lock: LockKind) -> Result<SharedArchive<T>, Error> {
+        let options = ResourceOptions {
+            lock,
+            ..ResourceOptions::default()
+        };
+        return Self::open_with_options(name, mode.map(|v| move || v), &options);
+    }
+
+    fn open_with_options<I: FnOnce() -> T>(
+        name: &str,
+        mode: OpenMode<I>,
+        options: &ResourceOptions,
+    ) -> Result<SharedArchive<T>, Error> {
+        // determine the OS
+        let shared_archive = match std::env::consts::OS {
+            "linux" => SharedArchive::Unix(UnixSharedArchive::<T>::open(name, mode, options)?),
+            "macos" => SharedArchive::Unix(UnixSharedArchive::<T>::open(name, mode, options)?),
+            _ => return Err(Error::UnsupportedOS),
+        };
+
+        return Ok(shared_archive);
+    }
+
+    /// Whether this handle created the value, rather than opening an existing one.
+    ///
+    pub fn created(&self) -> bool {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.resource().created()
+    }
+
+    /// Access the archived value in shared memory using a clojure, without deserializing it.
+    ///
+    /// The archive is checked the first time this handle reads it, and again after another
+    /// process changed it.
+    ///
+    /// #### Arguments
+    /// - `accessor`: A clojure that accepts a value of type `&Archived<T>` and returns a value of generic type `R`
+    ///
+    /// #### Returns
+    /// On success, returns the value of generic type `R`. If the value is not a valid
+    /// archive of `T`, returns `Error::ArchiveError`. On failure, returns an `Error`.
+    ///
+    pub fn access<F: FnOnce(&Archived<T>) -> R, R>(&self, accessor: F) -> Result<R, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.access_timeout(resource.resource().timeout(), accessor)
+    }
+
+    /// Access a mutable reference to a deserialized copy of the value using a clojure.
+    /// The value is archived again once the clojure returns.
+    ///
+    /// If the clojure panics, the value is left as it was, and the resource is poisoned
+    /// until a process calls `clear_poison`. If a process dies while holding the lock,
+    /// writing fails with `Error::OwnerDied` until a process calls `mark_consistent`.
+    ///
+    /// #### Arguments
+    /// - `accessor`: A clojure that accepts a value of type `&mut T` and returns a value of generic type `R`
+    ///
+    /// #### Returns
+    /// On success, returns the value of generic type `R`. On failure, returns an `Error`.
+    ///
+    pub fn access_mut<F: FnOnce(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.access_mut_timeout(resource.resource().timeout(), accessor)
+    }
+
+    /// Same as `access`, but waits for the lock according to `timeout` instead of the
+    /// timeout of the value.
+    ///
+    pub fn access_timeout<F: FnOnce(&Archived<T>) -> R, R>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<R, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.access_timeout(timeout, accessor)
+    }
+
+    /// Same as `access_mut`, but waits for the lock according to `timeout` instead of the
+    /// timeout of the value.
+    ///
+    pub fn access_mut_timeout<F: FnOnce(&mut T) -> D, D>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<D, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.access_mut_timeout(timeout, accessor)
+    }
+
+    /// Whether a process panicked while writing the value, and nobody cleared it since.
+    ///
+    pub fn is_poisoned(&self) -> bool {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.resource().is_poisoned()
+    }
+
+    /// Mark the value as sound again after a process panicked while writing it.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn clear_poison(&self) -> Result<(), Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.resource().clear_poison()
+    }
+
+    /// The process that died while holding the lock of the value, if nobody marked it
+    /// consistent since.
+    ///
+    pub fn owner_died(&self) -> Option<u32> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.resource().owner_died()
+    }
+
+    /// Mark the value as consistent again after a process died while holding its lock.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn mark_consistent(&self) -> Result<(), Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.resource().mark_consistent()
+    }
+
+    /// Set how long `access` and `access_mut` wait for the lock of this value.
+    /// Defaults to 5 seconds.
+    ///
+    pub fn set_timeout(&mut self, timeout: LockTimeout) {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.resource_mut().set_timeout(timeout)
+    }
+
+    /// Detach this process from the value, destroying it if this process is the last one
+    /// attached.
+    ///
+    /// #### Returns
+    /// On success, returns whether this process was the last one and what was unlinked.
+    /// On failure, returns an `Error`.
+    ///
+    pub fn close(self) -> Result<CloseOutcome, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.close()
+    }
+
+    /// Detach this process from the value and destroy it, even if other processes are
+    /// still attached.
+    ///
+    /// #### Returns
+    /// On success, returns what was unlinked. On failure, returns an `Error`.
+    ///
+    pub fn destroy(self) -> Result<CloseOutcome, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.destroy()
+    }
+}
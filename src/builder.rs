@@ -0,0 +1,180 @@
+//! ### Shared Resource Builder
+//!
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::codec::Codec;
+use crate::error::Error;
+use crate::options::{
+    CleanupPolicy, LockKind, LockTimeout, Migration, OpenMode, ReadMode, ResourceOptions,
+};
+use crate::SharedResource;
+
+/// Configuration of a shared resource, opened with `build`.
+///
+/// Unless an initial value is given, `build` only opens an existing resource.
+///
+pub struct SharedResourceBuilder<T: Serialize + DeserializeOwned> {
+    name: String,
+    mode: OpenMode<T>,
+    options: ResourceOptions,
+    migrate: Option<Migration<'static, T>>,
+}
+
+impl<T: Serialize + DeserializeOwned> SharedResourceBuilder<T> {
+    /// Start configuring the shared resource with the given name.
+    ///
+    pub fn new(name: &str) -> SharedResourceBuilder<T> {
+        return SharedResourceBuilder {
+            name: name.to_string(),
+            mode: OpenMode::OpenExisting,
+            options: ResourceOptions::default(),
+            migrate: None,
+        };
+    }
+
+    /// Set the name of the resource.
+    ///
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        return self;
+    }
+
+    /// Set whether to create the resource, open it, or both.
+    ///
+    pub fn mode(mut self, mode: OpenMode<T>) -> Self {
+        self.mode = mode;
+        return self;
+    }
+
+    /// Create the resource with `initial_value`, failing if it already exists.
+    ///
+    pub fn create_new(self, initial_value: T) -> Self {
+        return self.mode(OpenMode::CreateNew(initial_value));
+    }
+
+    /// Open the resource, failing if it does not exist.
+    ///
+    pub fn open_existing(self) -> Self {
+        return self.mode(OpenMode::OpenExisting);
+    }
+
+    /// Open the resource, creating it with `initial_value` if it does not exist.
+    ///
+    pub fn create_or_open(self, initial_value: T) -> Self {
+        return self.mode(OpenMode::CreateOrOpen(initial_value));
+    }
+
+    /// Set the permission bits of the shared memory segment and of the semaphores, when
+    /// this process creates them. The umask of the process applies. Defaults to `0o700`.
+    ///
+    pub fn permissions(mut self, permissions: u32) -> Self {
+        self.options.permissions = permissions;
+        return self;
+    }
+
+    /// Set the number of bytes reserved for the value when this process creates the
+    /// resource, so that the segment does not have to grow while the value stays smaller.
+    ///
+    pub fn initial_capacity(mut self, initial_capacity: usize) -> Self {
+        self.options.initial_capacity = initial_capacity;
+        return self;
+    }
+
+    /// Set the kind of lock guarding the resource, which must be the same in every process.
+    ///
+    pub fn lock(mut self, lock: LockKind) -> Self {
+        self.options.lock = lock;
+        return self;
+    }
+
+    /// Set how long opening, accessing and closing the resource wait for its lock.
+    /// Defaults to 5 seconds.
+    ///
+    pub fn timeout(mut self, timeout: LockTimeout) -> Self {
+        self.options.timeout = timeout;
+        return self;
+    }
+
+    /// Set how long opening the resource waits for the process creating it to run its
+    /// initializer, after which opening fails with `Error::Initializing`. A creator that
+    /// dies meanwhile is never waited for. Defaults to `LockTimeout::Forever`.
+    ///
+    pub fn init_timeout(mut self, timeout: LockTimeout) -> Self {
+        self.options.init_timeout = timeout;
+        return self;
+    }
+
+    /// Set what the last process attached does when it closes the resource.
+    ///
+    pub fn cleanup(mut self, cleanup: CleanupPolicy) -> Self {
+        self.options.cleanup = cleanup;
+        return self;
+    }
+
+    /// Set the format of the serialized value, which must be the same in every process.
+    /// Defaults to `Bincode`.
+    ///
+    pub fn codec(mut self, codec: &'static dyn Codec) -> Self {
+        self.options.codec = codec;
+        return self;
+    }
+
+    /// Set whether readers take the read lock, or copy the value without it as long as no
+    /// writer changes it meanwhile. Defaults to `ReadMode::Lock`.
+    ///
+    pub fn read_mode(mut self, read_mode: ReadMode) -> Self {
+        self.options.read_mode = read_mode;
+        return self;
+    }
+
+    /// Set whether a panic while writing the value marks the resource as poisoned, so that
+    /// every process gets `Error::Poisoned` until one of them calls `clear_poison`. The
+    /// half written value is thrown away either way. Defaults to `true`.
+    ///
+    pub fn poison_on_panic(mut self, poison_on_panic: bool) -> Self {
+        self.options.poison_on_panic = poison_on_panic;
+        return self;
+    }
+
+    /// Set the version of the layout of `T`, to be raised whenever the layout changes.
+    /// Opening a resource stored with another schema version fails with
+    /// `Error::SchemaMismatch`, unless the version is older and a migration is set with
+    /// `migrate`. Defaults to `0`.
+    ///
+    pub fn schema_version(mut self, schema_version: u32) -> Self {
+        self.options.schema_version = schema_version;
+        return self;
+    }
+
+    /// Set how to convert a value stored with an older schema version.
+    ///
+    /// The first process opening the resource with a newer schema version runs `migrate`
+    /// under the write lock, with the schema version of the stored value and its serialized
+    /// bytes, which `<dyn Codec>::decode` turns back into the old type. The returned value
+    /// replaces the stored one, and processes still using the old schema version get
+    /// `Error::SchemaMismatch` from then on instead of misreading it.
+    ///
+    pub fn migrate<F: FnOnce(u32, &[u8]) -> Result<T, Error> + 'static>(
+        mut self,
+        migrate: F,
+    ) -> Self {
+        self.migrate = Some(Box::new(migrate));
+        return self;
+    }
+
+    /// Open the resource as configured.
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedResource`. On failure, returns an `Error`, as described
+    /// for `SharedResource::open`.
+    ///
+    pub fn build(self) -> Result<SharedResource<T>, Error> {
+        return SharedResource::open_with_options(
+            &self.name,
+            self.mode.map(|v| move || v),
+            &self.options,
+            self.migrate,
+        );
+    }
+}
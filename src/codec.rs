@@ -0,0 +1,243 @@
+//! ### Serialization Codecs
+//!
+//! Formats in which the value of a shared resource is stored in shared memory.
+//!
+//! The creator of a resource records the `Codec::id` of its codec in the header of the
+//! segment, and every other process must open the resource with a codec of the same id.
+//! Bincode is always available; the other built-in codecs are enabled by the cargo feature of
+//! the same name. Any other format can be plugged in by implementing `Codec`.
+//!
+
+use erased_serde::Deserializer;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::Error;
+
+/// Callback through which a codec hands its deserializer to `Codec::decode_erased`.
+///
+pub type DecodeVisitor<'a> =
+    dyn for<'de> FnMut(&mut dyn Deserializer<'de>) -> Result<(), erased_serde::Error> + 'a;
+
+/// Format of the serialized value of a shared resource.
+///
+/// The trait is object safe, so that a codec can be stored in `ResourceOptions` whatever the
+/// type of the resource: it serializes through `erased_serde`. Use the generic
+/// `encode` and `decode` methods of `dyn Codec` to serialize a value of a known type.
+///
+/// Identifiers below 256 are reserved for the built-in codecs, a custom codec must return an
+/// identifier of 256 or more.
+///
+pub trait Codec: std::fmt::Debug + Sync + std::panic::RefUnwindSafe {
+    /// Stable identifier of the codec, as recorded in the header of the segment.
+    ///
+    fn id(&self) -> u32;
+
+    /// Name of the codec, as reported in errors.
+    ///
+    fn name(&self) -> &str;
+
+    /// Serialize a value.
+    ///
+    /// #### Returns
+    /// On success, returns the serialized bytes. On failure, returns an `Error`.
+    ///
+    fn encode_erased(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error>;
+
+    /// Deserialize a value, by passing a deserializer of the bytes to `visit`.
+    ///
+    /// #### Returns
+    /// On success, returns `Ok(())`. On failure, including when `visit` fails, returns an
+    /// `Error`.
+    ///
+    fn decode_erased(&self, bytes: &[u8], visit: &mut DecodeVisitor<'_>) -> Result<(), Error>;
+}
+
+impl dyn Codec {
+    /// Serialize a value.
+    ///
+    /// #### Returns
+    /// On success, returns the serialized bytes. On failure, returns an `Error`.
+    ///
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        return self.encode_erased(value);
+    }
+
+    /// Deserialize a value.
+    ///
+    /// #### Returns
+    /// On success, returns the value. On failure, returns an `Error`.
+    ///
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        let mut value: Option<T> = None;
+        self.decode_erased(bytes, &mut |deserializer| {
+            value = Some(erased_serde::deserialize::<T>(deserializer)?);
+            return Ok(());
+        })?;
+        return value.ok_or_else(|| error(self, "the codec did not deserialize a value"));
+    }
+}
+
+/// Name of the built-in codec with the given identifier, whether or not it is enabled.
+///
+pub(crate) fn describe(id: u32) -> String {
+    match id {
+        1 => return "bincode".to_string(),
+        2 => return "postcard".to_string(),
+        3 => return "msgpack".to_string(),
+        4 => return "cbor".to_string(),
+        5 => return "json".to_string(),
+        id => return format!("unknown codec {}", id),
+    }
+}
+
+/// Error of a codec that failed to serialize or deserialize a value.
+///
+fn error<E: std::fmt::Display>(codec: &dyn Codec, err: E) -> Error {
+    return Error::CodecError {
+        codec: codec.name().to_string(),
+        reason: err.to_string(),
+    };
+}
+
+/// Bincode, compact and fast, for resources only shared between Rust processes. The default.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn id(&self) -> u32 {
+        return 1;
+    }
+
+    fn name(&self) -> &str {
+        return "bincode";
+    }
+
+    fn encode_erased(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        return Ok(bincode::serialize(value)?);
+    }
+
+    fn decode_erased(&self, bytes: &[u8], visit: &mut DecodeVisitor<'_>) -> Result<(), Error> {
+        use bincode::Options;
+
+        // the options of `bincode::deserialize`
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes();
+        let mut deserializer = bincode::Deserializer::from_slice(bytes, options);
+        return visit(&mut <dyn Deserializer>::erase(&mut deserializer))
+            .map_err(|err| error(self, err));
+    }
+}
+
+/// Postcard, compact, for resources only shared between Rust processes, requires the
+/// `postcard` feature.
+///
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn id(&self) -> u32 {
+        return 2;
+    }
+
+    fn name(&self) -> &str {
+        return "postcard";
+    }
+
+    fn encode_erased(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        return postcard::to_stdvec(value).map_err(|err| error(self, err));
+    }
+
+    fn decode_erased(&self, bytes: &[u8], visit: &mut DecodeVisitor<'_>) -> Result<(), Error> {
+        let mut deserializer = postcard::Deserializer::from_bytes(bytes);
+        return visit(&mut <dyn Deserializer>::erase(&mut deserializer))
+            .map_err(|err| error(self, err));
+    }
+}
+
+/// MessagePack, readable from most languages, requires the `msgpack` feature.
+///
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn id(&self) -> u32 {
+        return 3;
+    }
+
+    fn name(&self) -> &str {
+        return "msgpack";
+    }
+
+    fn encode_erased(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        return rmp_serde::to_vec(value).map_err(|err| error(self, err));
+    }
+
+    fn decode_erased(&self, bytes: &[u8], visit: &mut DecodeVisitor<'_>) -> Result<(), Error> {
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(bytes);
+        return visit(&mut <dyn Deserializer>::erase(&mut deserializer))
+            .map_err(|err| error(self, err));
+    }
+}
+
+/// CBOR, readable from most languages, requires the `cbor` feature.
+///
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn id(&self) -> u32 {
+        return 4;
+    }
+
+    fn name(&self) -> &str {
+        return "cbor";
+    }
+
+    fn encode_erased(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        let mut bytes: Vec<u8> = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(|err| error(self, err))?;
+        return Ok(bytes);
+    }
+
+    fn decode_erased(&self, bytes: &[u8], visit: &mut DecodeVisitor<'_>) -> Result<(), Error> {
+        // ciborium does not expose its deserializer, go through an intermediate value
+        let value: serde_value::Value =
+            ciborium::from_reader(bytes).map_err(|err| error(self, err))?;
+        return visit(&mut <dyn Deserializer>::erase(value)).map_err(|err| error(self, err));
+    }
+}
+
+/// JSON, readable by anything, requires the `json` feature.
+///
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn id(&self) -> u32 {
+        return 5;
+    }
+
+    fn name(&self) -> &str {
+        return "json";
+    }
+
+    fn encode_erased(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        return serde_json::to_vec(value).map_err(|err| error(self, err));
+    }
+
+    fn decode_erased(&self, bytes: &[u8], visit: &mut DecodeVisitor<'_>) -> Result<(), Error> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        visit(&mut <dyn Deserializer>::erase(&mut deserializer)).map_err(|err| error(self, err))?;
+        return deserializer.end().map_err(|err| error(self, err));
+    }
+}
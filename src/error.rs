@@ -1,5 +1,7 @@
 //! ### Shared Resource Error
-//! 
+//!
+
+use std::time::Duration;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -7,8 +9,49 @@ pub enum Error {
     SemaphoreError(i32, String),
     #[error("[shared memory error] [errno {0}] {1}")]
     SharedMemoryError(i32, String),
+    #[error("[mutex error] [errno {0}] {1}")]
+    MutexError(i32, String),
+    #[error("[already exists] {name} already exists")]
+    AlreadyExists { name: String },
+    #[error("[not found] {name} does not exist")]
+    NotFound { name: String },
+    #[error("[shared memory error] incompatible segment {name}: {reason}")]
+    IncompatibleSegment { name: String, reason: String },
+    #[error("[timeout] waited {waited:?} for the lock of {name}")]
+    Timeout { name: String, waited: Duration },
+    #[error("[initializing] waited {waited:?} for the creator of {name} to initialize it")]
+    Initializing { name: String, waited: Duration },
+    #[error("[would block] the lock of {name} is held")]
+    WouldBlock { name: String },
+    #[error("[owner died] process {pid} died holding the lock of {name}")]
+    OwnerDied { name: String, pid: u32 },
+    #[error("[poisoned] process {pid} panicked while writing {name}")]
+    Poisoned { name: String, pid: u32 },
+    #[error("[too many processes] {name} cannot have more than {limit} handles attached")]
+    TooManyProcesses { name: String, limit: usize },
+    #[error("[codec mismatch] {name} was written with {found}, not {expected}")]
+    CodecMismatch {
+        name: String,
+        expected: String,
+        found: String,
+    },
+    #[error("[type mismatch] the resource holds {found}, not {expected}")]
+    TypeMismatch { expected: String, found: String },
+    #[error("[schema mismatch] {name} holds schema version {found}, not {expected}")]
+    SchemaMismatch {
+        name: String,
+        expected: u32,
+        found: u32,
+    },
+    #[error("[corrupted] {name} does not match its checksum")]
+    Corrupted { name: String },
     #[error("[bincode error]")]
     BincodeError(#[from] bincode::Error),
+    #[error("[codec error] [{codec}] {reason}")]
+    CodecError { codec: String, reason: String },
+    #[cfg(feature = "rkyv")]
+    #[error("[archive error] {0}")]
+    ArchiveError(#[from] rkyv::rancor::Error),
     #[error("unsupported operating system")]
     UnsupportedOS,
 }
@@ -25,12 +68,23 @@ impl Error {
 
         return Error::SharedMemoryError(errno, message);
     }
+
+    /// Build an error from the code returned by a pthread function, which does not set errno.
+    ///
+    pub fn mutex_error(code: i32) -> Error {
+        use libc::strerror;
+        use std::ffi::CStr;
+
+        let message = unsafe { CStr::from_ptr(strerror(code)).to_string_lossy().to_string() };
+
+        return Error::MutexError(code, message);
+    }
 }
 
 pub fn get_unix_errno() -> i32 {
     use libc::__errno_location;
 
-    return unsafe { *__errno_location().clone() };
+    return unsafe { *__errno_location() };
 }
 
 pub fn get_unix_error() -> (i32, String) {
@@ -39,10 +93,10 @@ pub fn get_unix_error() -> (i32, String) {
 
     let (errno, message) = unsafe {
         let errno = *__errno_location();
-        let message = strerror(errno.clone());
+        let message = strerror(errno);
         let message = CStr::from_ptr(message).to_string_lossy().to_string();
-        (errno,message)
+        (errno, message)
     };
 
     return (errno, message);
-}
\ No newline at end of file
+}
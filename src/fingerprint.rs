@@ -0,0 +1,71 @@
+//! ### Type Fingerprints
+//!
+//! Identity of the type stored in a shared resource, recorded in the header of the segment
+//! by the creator so that processes opening the resource with another type are turned away.
+//!
+//! The fingerprint of a serde type hashes the shape traced from its `Deserialize` impl:
+//! container names, field names and field formats, but not module paths, so the same type
+//! compiled into different binaries has the same fingerprint. Types that cannot be traced,
+//! and types that are not stored through serde, fall back to their type name and layout.
+//!
+
+use serde::de::DeserializeOwned;
+use serde_reflection::{FormatHolder, Tracer, TracerConfig};
+
+/// Name and schema hash of the type stored in a shared resource.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TypeFingerprint {
+    pub name: &'static str,
+    pub hash: u64,
+}
+
+impl TypeFingerprint {
+    /// Fingerprint of a type stored through serde.
+    ///
+    pub fn of_serde<T: DeserializeOwned>() -> TypeFingerprint {
+        let mut tracer = Tracer::new(TracerConfig::default());
+        let schema = tracer
+            .trace_simple_type::<T>()
+            .ok()
+            .and_then(|(mut format, _)| {
+                format.normalize().ok()?;
+                let registry = tracer.registry().ok()?;
+                return bincode::serialize(&(format, registry)).ok();
+            });
+
+        return match schema {
+            Some(schema) => TypeFingerprint {
+                name: std::any::type_name::<T>(),
+                hash: fnv1a(&schema),
+            },
+            None => Self::of_layout::<T>(),
+        };
+    }
+
+    /// Fingerprint of a type stored as its own bytes, from its name and layout.
+    ///
+    pub fn of_layout<T>() -> TypeFingerprint {
+        let name = std::any::type_name::<T>();
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.extend_from_slice(&(std::mem::size_of::<T>() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(std::mem::align_of::<T>() as u64).to_le_bytes());
+
+        return TypeFingerprint {
+            name,
+            hash: fnv1a(&bytes),
+        };
+    }
+}
+
+/// 64-bit FNV-1a hash, which unlike the hasher of the standard library is the same in
+/// every build.
+///
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
@@ -1,19 +1,63 @@
 //! ## Shared Resource IPC
 //!
-//! A resource shared across processes. Supports any number of processes.
+//! A resource shared across processes. Supports up to 128 handles attached to a resource at
+//! the same time, across every process, further handles fail to open it with
+//! `Error::TooManyProcesses`.
 //!
 
-use error::Error;
+#![allow(
+    clippy::needless_return,
+    clippy::module_inception,
+    clippy::enum_variant_names
+)]
+
 use serde::{de::DeserializeOwned, Serialize};
 
 mod unix {
+    #[cfg(feature = "rkyv")]
+    pub mod archive;
+    pub mod guard;
+    pub mod header;
+    pub mod mutex;
+    pub mod process;
     pub mod semaphore;
     pub mod shared_mem;
     pub mod unix;
 }
 
+#[cfg(feature = "rkyv")]
+mod archive;
+mod builder;
+mod codec;
 mod error;
+mod fingerprint;
+mod options;
+mod outcome;
+mod pod;
+
+#[cfg(feature = "rkyv")]
+pub use archive::SharedArchive;
+pub use builder::SharedResourceBuilder;
+#[cfg(feature = "cbor")]
+pub use codec::Cbor;
+#[cfg(feature = "json")]
+pub use codec::Json;
+#[cfg(feature = "msgpack")]
+pub use codec::MessagePack;
+#[cfg(feature = "postcard")]
+pub use codec::Postcard;
+pub use codec::{Bincode, Codec, DecodeVisitor};
+/// the version of `erased_serde` that custom `Codec` implementations serialize through
+pub use erased_serde;
+pub use error::Error;
+pub use options::{CleanupPolicy, LockKind, LockTimeout, OpenMode, ReadMode};
+pub use outcome::CloseOutcome;
+pub use pod::SharedPod;
+#[cfg(feature = "rkyv")]
+pub use unix::archive::Archivable;
+pub use unix::guard::{ReadGuard, WriteGuard};
 
+use options::{Migration, ResourceOptions};
 use unix::unix::UnixSharedResource;
 
 trait SharedResourceBackend<T: Serialize + DeserializeOwned> {
@@ -26,7 +70,7 @@ trait SharedResourceBackend<T: Serialize + DeserializeOwned> {
     /// #### Returns
     /// On success, returns the value of generic type `R`. On failure, returns an `Error`.
     ///
-    fn access<F: Fn(&T) -> R, R>(&self, accessor: F) -> Result<R, Error>;
+    fn access<F: FnOnce(&T) -> R, R>(&self, accessor: F) -> Result<R, Error>;
 
     /// Access a mutable reference to the shared resource using a clojure.
     /// The clojure can return a value based on the reference to the resource.
@@ -37,7 +81,70 @@ trait SharedResourceBackend<T: Serialize + DeserializeOwned> {
     /// #### Returns
     /// On success, returns the value of generic type `R`. On failure, returns an `Error`.
     ///
-    fn access_mut<F: Fn(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error>;
+    fn access_mut<F: FnOnce(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error>;
+
+    /// Same as `access`, but waits for the lock according to `timeout` instead of the
+    /// timeout of the resource.
+    ///
+    fn access_timeout<F: FnOnce(&T) -> R, R>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<R, Error>;
+
+    /// Same as `access_mut`, but waits for the lock according to `timeout` instead of the
+    /// timeout of the resource.
+    ///
+    fn access_mut_timeout<F: FnOnce(&mut T) -> D, D>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<D, Error>;
+
+    /// Same as `access`, but fails with `Error::WouldBlock` instead of waiting when the lock
+    /// is held.
+    ///
+    fn try_access<F: FnOnce(&T) -> R, R>(&self, accessor: F) -> Result<R, Error>;
+
+    /// Same as `access_mut`, but fails with `Error::WouldBlock` instead of waiting when the
+    /// lock is held.
+    ///
+    fn try_access_mut<F: FnOnce(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error>;
+
+    /// Same as `access_mut`, but the clojure may fail, in which case the resource is left
+    /// as it was before the clojure ran.
+    ///
+    fn access_mut_fallible<F: FnOnce(&mut T) -> Result<D, E>, D, E: From<Error>>(
+        &self,
+        accessor: F,
+    ) -> Result<D, E>;
+
+    /// Lock the shared resource for reading until the returned guard is dropped.
+    ///
+    /// #### Returns
+    /// On success, returns a `ReadGuard` that derefs to the value of the resource. On
+    /// failure, returns an `Error`.
+    ///
+    fn read(&self) -> Result<ReadGuard<'_, T>, Error>;
+
+    /// Lock the shared resource for writing until the returned guard is dropped, committed
+    /// or aborted.
+    ///
+    /// #### Returns
+    /// On success, returns a `WriteGuard` that derefs to the value of the resource. On
+    /// failure, returns an `Error`.
+    ///
+    fn write(&self) -> Result<WriteGuard<'_, T>, Error>;
+
+    /// Same as `read`, but waits for the lock according to `timeout` instead of the
+    /// timeout of the resource.
+    ///
+    fn read_timeout(&self, timeout: LockTimeout) -> Result<ReadGuard<'_, T>, Error>;
+
+    /// Same as `write`, but waits for the lock according to `timeout` instead of the
+    /// timeout of the resource.
+    ///
+    fn write_timeout(&self, timeout: LockTimeout) -> Result<WriteGuard<'_, T>, Error>;
 }
 
 pub enum SharedResource<T: Serialize + DeserializeOwned> {
@@ -45,27 +152,157 @@ pub enum SharedResource<T: Serialize + DeserializeOwned> {
 }
 
 impl<T: Serialize + DeserializeOwned> SharedResource<T> {
+    /// Open the shared resource with the given name, creating it with `initial_value` if
+    /// it does not exist yet. If the resource holds a value of another type, returns
+    /// `Error::TypeMismatch`.
+    ///
     pub fn new(name: &str, initial_value: T) -> Result<SharedResource<T>, Error> {
+        return Self::with_lock(name, initial_value, LockKind::default());
+    }
+
+    /// Open the shared resource with the given name, guarded by the given kind of lock,
+    /// creating it with `initial_value` if it does not exist yet.
+    ///
+    /// #### Arguments
+    /// - `name`: name of the resource
+    /// - `initial_value`: value of the resource if this process creates it
+    /// - `lock`: kind of lock guarding the resource, the same in every process
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedResource`. If the resource exists with another type,
+    /// returns `Error::TypeMismatch`. If it exists with another kind of lock, returns
+    /// `Error::IncompatibleSegment`. On failure, returns an `Error`.
+    ///
+    pub fn with_lock(
+        name: &str,
+        initial_value: T,
+        lock: LockKind,
+    ) -> Result<SharedResource<T>, Error> {
+        return Self::open(name, OpenMode::CreateOrOpen(initial_value), lock);
+    }
+
+    /// Open the shared resource with the given name, creating it with the value built by
+    /// `init` if it does not exist yet.
+    ///
+    /// `init` only runs in the process that creates the resource, before any other process
+    /// can access it. Processes opening the resource meanwhile wait for `init` to return,
+    /// for as long as the default timeout allows. If `init` panics, or the process dies
+    /// before `init` returns, the resource is left for the next process to create.
+    ///
+    /// #### Arguments
+    /// - `name`: name of the resource
+    /// - `init`: A clojure that builds the value of the resource if this process creates it
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedResource`. If the process creating the resource does not
+    /// finish initializing it within the init timeout, returns `Error::Initializing`. On
+    /// failure, returns an `Error`.
+    ///
+    pub fn open_or_init<F: FnOnce() -> T>(name: &str, init: F) -> Result<SharedResource<T>, Error> {
+        return Self::open_with_options(
+            name,
+            OpenMode::CreateOrOpen(init),
+            &ResourceOptions::default(),
+            None,
+        );
+    }
+
+    /// Create the shared resource with the given name, failing if it already exists.
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedResource`. If the resource exists, returns
+    /// `Error::AlreadyExists`. On failure, returns an `Error`.
+    ///
+    pub fn create_new(name: &str, initial_value: T) -> Result<SharedResource<T>, Error> {
+        return Self::open(
+            name,
+            OpenMode::CreateNew(initial_value),
+            LockKind::default(),
+        );
+    }
+
+    /// Open the existing shared resource with the given name, failing if it does not exist.
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedResource`. If the resource does not exist, returns
+    /// `Error::NotFound`. On failure, returns an `Error`.
+    ///
+    pub fn open_existing(name: &str) -> Result<SharedResource<T>, Error> {
+        return Self::open(name, OpenMode::OpenExisting, LockKind::default());
+    }
+
+    /// Open the shared resource with the given name.
+    ///
+    /// #### Arguments
+    /// - `name`: name of the resource
+    /// - `mode`: whether to create the resource, open it, or both, with the value of a new
+    ///   resource
+    /// - `lock`: kind of lock guarding the resource, the same in every process
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedResource`, which tells with `created` whether it
+    /// created the resource. If the mode forbids creating or opening the resource, returns
+    /// `Error::AlreadyExists` or `Error::NotFound`. If the resource exists with another
+    /// type, returns `Error::TypeMismatch`. If it exists with another kind of lock, returns
+    /// `Error::IncompatibleSegment`. On failure, returns an `Error`.
+    ///
+    pub fn open(name: &str, mode: OpenMode<T>, lock: LockKind) -> Result<SharedResource<T>, Error> {
+        let options = ResourceOptions {
+            lock,
+            ..ResourceOptions::default()
+        };
+        return Self::open_with_options(name, mode.map(|v| move || v), &options, None);
+    }
+
+    /// Configure the shared resource with the given name before opening it.
+    ///
+    pub fn builder(name: &str) -> SharedResourceBuilder<T> {
+        return SharedResourceBuilder::new(name);
+    }
+
+    fn open_with_options<I: FnOnce() -> T>(
+        name: &str,
+        mode: OpenMode<I>,
+        options: &ResourceOptions,
+        migrate: Option<Migration<'_, T>>,
+    ) -> Result<SharedResource<T>, Error> {
         // determine the OS
         let shared_resource = match std::env::consts::OS {
-            "linux" => SharedResource::Unix(UnixSharedResource::<T>::new(name, initial_value)?),
-            "macos" => SharedResource::Unix(UnixSharedResource::<T>::new(name, initial_value)?),
+            "linux" => SharedResource::Unix(UnixSharedResource::<T>::open_with(
+                name, mode, options, migrate,
+            )?),
+            "macos" => SharedResource::Unix(UnixSharedResource::<T>::open_with(
+                name, mode, options, migrate,
+            )?),
             _ => return Err(Error::UnsupportedOS),
         };
 
         return Ok(shared_resource);
     }
 
+    /// Whether this handle created the resource, rather than opening an existing one.
+    ///
+    pub fn created(&self) -> bool {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.created()
+    }
+
     /// Access an immutable reference to the shared resource using a clojure.
     /// The clojure can return a value based on the reference to the resource.
     ///
+    /// Any number of processes can read the resource at the same time. Readers only wait
+    /// for a process that is mutating the resource.
+    ///
     /// #### Arguments
     /// - `accessor`: A clojure that accepts a value of type `&T` and returns a value of generic type `R`
     ///
     /// #### Returns
-    /// On success, returns the value of generic type `R`. On failure, returns an `Error`.
+    /// On success, returns the value of generic type `R`. If a writer died halfway through
+    /// writing the value, returns `Error::Corrupted`. On failure, returns an `Error`.
     ///
-    pub fn access<F: Fn(&T) -> R, R>(&self, accessor: F) -> Result<R, Error> {
+    pub fn access<F: FnOnce(&T) -> R, R>(&self, accessor: F) -> Result<R, Error> {
         let resource = match self {
             Self::Unix(res) => res,
         };
@@ -75,16 +312,301 @@ impl<T: Serialize + DeserializeOwned> SharedResource<T> {
     /// Access a mutable reference to the shared resource using a clojure.
     /// The clojure can return a value based on the reference to the resource.
     ///
+    /// If a process dies while holding the lock, writing fails with `Error::OwnerDied`,
+    /// without running the closure, until a process checks the value under the lock of a
+    /// `WriteGuard` and calls `WriteGuard::mark_consistent`, or calls `mark_consistent`.
+    ///
+    /// If the clojure panics, the lock is released and the value is left as it was. Unless
+    /// configured otherwise, the resource is then poisoned: every access fails with
+    /// `Error::Poisoned` until a process calls `clear_poison`.
+    ///
     /// #### Arguments
     /// - `accessor`: A clojure that accepts a value of type `&mut T` and returns a value of generic type `R`
     ///
     /// #### Returns
     /// On success, returns the value of generic type `R`. On failure, returns an `Error`.
     ///
-    pub fn access_mut<F: Fn(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error> {
+    pub fn access_mut<F: FnOnce(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error> {
         let resource = match self {
             Self::Unix(res) => res,
         };
         resource.access_mut(accessor)
     }
+
+    /// Access an immutable reference to the shared resource using a clojure, waiting for
+    /// the lock according to `timeout` instead of the timeout of the resource.
+    ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the lock
+    /// - `accessor`: A clojure that accepts a value of type `&T` and returns a value of generic type `R`
+    ///
+    /// #### Returns
+    /// On success, returns the value of generic type `R`. If the lock could not be taken in
+    /// time, returns `Error::Timeout`. On failure, returns an `Error`.
+    ///
+    pub fn access_timeout<F: FnOnce(&T) -> R, R>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<R, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.access_timeout(timeout, accessor)
+    }
+
+    /// Access a mutable reference to the shared resource using a clojure, waiting for
+    /// the lock according to `timeout` instead of the timeout of the resource.
+    ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the lock
+    /// - `accessor`: A clojure that accepts a value of type `&mut T` and returns a value of generic type `R`
+    ///
+    /// #### Returns
+    /// On success, returns the value of generic type `R`. If the lock could not be taken in
+    /// time, returns `Error::Timeout`. On failure, returns an `Error`.
+    ///
+    pub fn access_mut_timeout<F: FnOnce(&mut T) -> D, D>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<D, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.access_mut_timeout(timeout, accessor)
+    }
+
+    /// Access an immutable reference to the shared resource using a clojure, without
+    /// waiting for the lock.
+    ///
+    /// #### Arguments
+    /// - `accessor`: A clojure that accepts a value of type `&T` and returns a value of generic type `R`
+    ///
+    /// #### Returns
+    /// On success, returns the value of generic type `R`. If another process holds the lock,
+    /// returns `Error::WouldBlock`. On failure, returns an `Error`.
+    ///
+    pub fn try_access<F: FnOnce(&T) -> R, R>(&self, accessor: F) -> Result<R, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.try_access(accessor)
+    }
+
+    /// Access a mutable reference to the shared resource using a clojure, without
+    /// waiting for the lock.
+    ///
+    /// #### Arguments
+    /// - `accessor`: A clojure that accepts a value of type `&mut T` and returns a value of generic type `R`
+    ///
+    /// #### Returns
+    /// On success, returns the value of generic type `R`. If another process holds the lock,
+    /// returns `Error::WouldBlock`. On failure, returns an `Error`.
+    ///
+    pub fn try_access_mut<F: FnOnce(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.try_access_mut(accessor)
+    }
+
+    /// Access a mutable reference to the shared resource using a clojure that may fail.
+    ///
+    /// The value is only written back to the resource when the clojure returns `Ok`, so a
+    /// clojure returning `Err` halfway through its changes leaves the resource as it was.
+    /// Errors of the resource itself are converted into the error type of the clojure.
+    ///
+    /// #### Arguments
+    /// - `accessor`: A clojure that accepts a value of type `&mut T` and returns a
+    ///   `Result<D, E>`
+    ///
+    /// #### Returns
+    /// On success, returns the value of generic type `D`. On failure, returns the error of
+    /// the clojure, or an `Error` converted into `E`.
+    ///
+    pub fn access_mut_fallible<F: FnOnce(&mut T) -> Result<D, E>, D, E: From<Error>>(
+        &self,
+        accessor: F,
+    ) -> Result<D, E> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.access_mut_fallible(accessor)
+    }
+
+    /// Lock the shared resource for reading until the returned guard is dropped.
+    ///
+    /// Unlike `access`, the value can be used across statements and early returns. The
+    /// guard holds a copy of the value, read when the lock was taken.
+    ///
+    /// #### Returns
+    /// On success, returns a `ReadGuard` that derefs to `&T`. On failure, returns an
+    /// `Error`.
+    ///
+    pub fn read(&self) -> Result<ReadGuard<'_, T>, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.read()
+    }
+
+    /// Lock the shared resource for writing until the returned guard is dropped.
+    ///
+    /// The guard derefs to a copy of the value, which is written back to the resource when
+    /// the guard is dropped or committed with `WriteGuard::commit`, and thrown away when it
+    /// is aborted with `WriteGuard::abort`.
+    ///
+    /// If a process died while holding the lock, the guard reports it through
+    /// `WriteGuard::owner_died`, so that the value can be checked before the guard is
+    /// marked consistent. Until then, the guard does not write the value back.
+    ///
+    /// #### Returns
+    /// On success, returns a `WriteGuard` that derefs to `&mut T`. On failure, returns an
+    /// `Error`.
+    ///
+    pub fn write(&self) -> Result<WriteGuard<'_, T>, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.write()
+    }
+
+    /// Lock the shared resource for reading, waiting for the lock according to `timeout`
+    /// instead of the timeout of the resource.
+    ///
+    /// #### Returns
+    /// On success, returns a `ReadGuard`. If the lock could not be taken in time, returns
+    /// `Error::Timeout`. On failure, returns an `Error`.
+    ///
+    pub fn read_timeout(&self, timeout: LockTimeout) -> Result<ReadGuard<'_, T>, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.read_timeout(timeout)
+    }
+
+    /// Lock the shared resource for writing, waiting for the lock according to `timeout`
+    /// instead of the timeout of the resource.
+    ///
+    /// #### Returns
+    /// On success, returns a `WriteGuard`. If the lock could not be taken in time, returns
+    /// `Error::Timeout`. On failure, returns an `Error`.
+    ///
+    pub fn write_timeout(&self, timeout: LockTimeout) -> Result<WriteGuard<'_, T>, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.write_timeout(timeout)
+    }
+
+    /// Whether a process panicked while writing the value of the resource, and nobody
+    /// cleared the poison since.
+    ///
+    pub fn is_poisoned(&self) -> bool {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.is_poisoned()
+    }
+
+    /// Mark the value of the resource as sound again after a process panicked while
+    /// writing it, so that it can be accessed, and checked, again.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn clear_poison(&self) -> Result<(), Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.clear_poison()
+    }
+
+    /// The process that died while holding the lock of the resource, if nobody marked the
+    /// value consistent since.
+    ///
+    pub fn owner_died(&self) -> Option<u32> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.owner_died()
+    }
+
+    /// Mark the value of the resource as consistent again after a process died while
+    /// holding its lock, so that it can be written again.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn mark_consistent(&self) -> Result<(), Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.mark_consistent()
+    }
+
+    /// Set how long `access` and `access_mut` wait for the lock of this resource.
+    /// Defaults to 5 seconds.
+    ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the lock
+    ///
+    pub fn set_timeout(&mut self, timeout: LockTimeout) {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.set_timeout(timeout)
+    }
+
+    /// Set whether this handle takes the read lock to read the resource, or copies the
+    /// value without it as long as no writer changes it meanwhile. Defaults to
+    /// `ReadMode::Lock`.
+    ///
+    /// With `ReadMode::SeqLock`, a read that writers keep getting in the way of for more
+    /// than a millisecond falls back to the read lock, and so waits for it up to the timeout
+    /// of this handle.
+    ///
+    /// #### Arguments
+    /// - `read_mode`: how to read the resource
+    ///
+    pub fn set_read_mode(&mut self, read_mode: ReadMode) {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.set_read_mode(read_mode)
+    }
+
+    /// Detach this process from the resource. The last process attached destroys the
+    /// resource, unlinking its shared memory segment and its lock.
+    ///
+    /// Dropping the resource does the same, but only logs failures.
+    ///
+    /// #### Returns
+    /// On success, returns whether this process was the last one and what was unlinked.
+    /// On failure, returns an `Error`, after releasing what this process could release.
+    ///
+    pub fn close(self) -> Result<CloseOutcome, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.close()
+    }
+
+    /// Detach this process from the resource and destroy the resource, even if other
+    /// processes are still attached.
+    ///
+    /// Processes still attached keep working on the destroyed resource, while processes
+    /// opening the resource afterwards get a new one.
+    ///
+    /// #### Returns
+    /// On success, returns whether this process was the last one and what was unlinked.
+    /// On failure, returns an `Error`, after releasing what this process could release.
+    ///
+    pub fn destroy(self) -> Result<CloseOutcome, Error> {
+        let resource = match self {
+            Self::Unix(res) => res,
+        };
+        resource.destroy()
+    }
 }
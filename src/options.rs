@@ -0,0 +1,215 @@
+//! ### Shared Resource Options
+//!
+
+use std::time::{Duration, Instant};
+
+use crate::codec::{Bincode, Codec};
+use crate::error::Error;
+
+/// How long to wait for the lock of a shared resource.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockTimeout {
+    /// Wait until the lock is available, however long it takes.
+    Forever,
+    /// Do not wait: fail right away with `Error::WouldBlock` if the lock is held.
+    NoWait,
+    /// Wait at most the given duration, then fail with `Error::Timeout`.
+    After(Duration),
+}
+
+impl Default for LockTimeout {
+    fn default() -> Self {
+        return LockTimeout::After(Duration::from_secs(5));
+    }
+}
+
+impl LockTimeout {
+    /// The part of this timeout left after waiting since `start`.
+    ///
+    pub(crate) fn remaining(&self, start: Instant) -> LockTimeout {
+        match self {
+            LockTimeout::After(duration) => {
+                LockTimeout::After(duration.saturating_sub(start.elapsed()))
+            }
+            other => *other,
+        }
+    }
+
+    /// Whether this timeout expired after waiting since `start`.
+    ///
+    pub(crate) fn is_expired(&self, start: Instant) -> bool {
+        match self.remaining(start) {
+            LockTimeout::Forever => return false,
+            LockTimeout::NoWait => return true,
+            LockTimeout::After(duration) => return duration.is_zero(),
+        }
+    }
+
+    /// The absolute deadline on the realtime clock at which this timeout expires, as
+    /// expected by `sem_timedwait` and `pthread_mutex_timedlock`.
+    ///
+    pub(crate) fn deadline(&self) -> Option<libc::timespec> {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        match self {
+            LockTimeout::After(duration) => {
+                let deadline = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    + *duration;
+                Some(libc::timespec {
+                    tv_sec: deadline.as_secs() as libc::time_t,
+                    tv_nsec: deadline.subsec_nanos() as libc::c_long,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The kind of lock that guards a shared resource.
+///
+/// Every process opening the same resource must use the same kind of lock.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// Reader-writer lock made of named semaphores. Any number of processes can read the
+    /// resource at the same time.
+    RwSemaphore {
+        /// whether a waiting writer stops new readers from entering
+        writer_preference: bool,
+    },
+    /// Robust mutex stored inside the shared memory segment, so that the lock and the
+    /// data share one lifecycle. When its holder dies, the OS hands the mutex over to the
+    /// next process right away. Readers take the mutex exclusively.
+    RobustMutex,
+}
+
+impl Default for LockKind {
+    fn default() -> Self {
+        return LockKind::RwSemaphore {
+            writer_preference: true,
+        };
+    }
+}
+
+impl LockKind {
+    /// Identifier of the kind of lock, as stored in the header of the segment.
+    ///
+    pub(crate) fn id(&self) -> u32 {
+        match self {
+            LockKind::RwSemaphore { .. } => 1,
+            LockKind::RobustMutex => 2,
+        }
+    }
+}
+
+/// Whether opening a shared resource may create it, open it, or both.
+///
+/// The modes that may create the resource carry its initial value, or a closure building
+/// the initial value.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode<T> {
+    /// Create the resource, failing with `Error::AlreadyExists` if it exists.
+    CreateNew(T),
+    /// Open the resource, failing with `Error::NotFound` if it does not exist.
+    OpenExisting,
+    /// Open the resource, creating it if it does not exist.
+    CreateOrOpen(T),
+}
+
+impl<T> OpenMode<T> {
+    /// Transform the initial value carried by the mode.
+    ///
+    pub(crate) fn map<U, F: FnOnce(T) -> U>(self, f: F) -> OpenMode<U> {
+        match self {
+            OpenMode::CreateNew(initial_value) => OpenMode::CreateNew(f(initial_value)),
+            OpenMode::OpenExisting => OpenMode::OpenExisting,
+            OpenMode::CreateOrOpen(initial_value) => OpenMode::CreateOrOpen(f(initial_value)),
+        }
+    }
+
+    /// Mutably borrow the initial value carried by the mode.
+    ///
+    pub(crate) fn as_mut(&mut self) -> OpenMode<&mut T> {
+        match self {
+            OpenMode::CreateNew(initial_value) => OpenMode::CreateNew(initial_value),
+            OpenMode::OpenExisting => OpenMode::OpenExisting,
+            OpenMode::CreateOrOpen(initial_value) => OpenMode::CreateOrOpen(initial_value),
+        }
+    }
+}
+
+/// What the last process attached to a shared resource does when it closes its handle.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CleanupPolicy {
+    /// Unlink the shared memory segment and the lock, so that the next process to open the
+    /// resource starts afresh.
+    #[default]
+    DestroyWhenLast,
+    /// Leave the segment and the lock behind, so that the value outlives every process.
+    /// Only `destroy` unlinks them.
+    Keep,
+}
+
+/// How a handle reads the value of a resource.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadMode {
+    /// readers take the read lock
+    #[default]
+    Lock,
+    /// readers copy the value without taking the lock, and copy it again if a writer
+    /// changed it meanwhile. Writers still take the write lock. Suits small values read
+    /// by many processes. A reader that writers keep getting in the way of for more than a
+    /// millisecond takes the read lock instead, waiting for it up to the lock timeout.
+    SeqLock,
+}
+
+/// Hook converting a value stored with an older schema version, given that version and
+/// the serialized value.
+///
+pub(crate) type Migration<'a, T> = Box<dyn FnOnce(u32, &[u8]) -> Result<T, Error> + 'a>;
+
+/// Configuration of a shared resource, as set through `SharedResourceBuilder`.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceOptions {
+    /// permission bits of the shared memory segment and the semaphores, subject to the umask
+    pub permissions: u32,
+    /// number of bytes reserved for the value when creating the resource
+    pub initial_capacity: usize,
+    pub lock: LockKind,
+    pub timeout: LockTimeout,
+    /// how long opening waits for the creator of the resource to initialize it
+    pub init_timeout: LockTimeout,
+    pub cleanup: CleanupPolicy,
+    /// format of the serialized value, the same in every process
+    pub codec: &'static dyn Codec,
+    /// whether a panic while writing the value marks the resource as poisoned
+    pub poison_on_panic: bool,
+    /// version of the layout of the value, raised by a migration when it changes
+    pub schema_version: u32,
+    /// whether readers take the read lock
+    pub read_mode: ReadMode,
+}
+
+impl Default for ResourceOptions {
+    fn default() -> Self {
+        return ResourceOptions {
+            permissions: 0o700,
+            initial_capacity: 0,
+            lock: LockKind::default(),
+            timeout: LockTimeout::default(),
+            init_timeout: LockTimeout::Forever,
+            cleanup: CleanupPolicy::default(),
+            codec: &Bincode,
+            poison_on_panic: true,
+            schema_version: 0,
+            read_mode: ReadMode::default(),
+        };
+    }
+}
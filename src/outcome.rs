@@ -0,0 +1,15 @@
+//! ### Shared Resource Outcomes
+//!
+
+/// What happened when a process closed its handle to a shared resource.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CloseOutcome {
+    /// whether this handle was the last one attached to the resource, in any process
+    pub was_final: bool,
+    /// whether the shared memory segment was unlinked
+    pub unlinked_memory: bool,
+    /// whether the named semaphores of the lock were unlinked, never the case for a
+    /// `LockKind::RobustMutex`, which lives in the segment
+    pub unlinked_lock: bool,
+}
@@ -0,0 +1,174 @@
+//! ## Archived Resource
+//!
+//! A resource whose value is archived with rkyv, so that readers use it in place over the
+//! bytes of the segment instead of deserializing it.
+//!
+//! The archive is checked the first time this handle reads it, and again whenever another
+//! process wrote or resized it since. Reads of an archive that was already checked go
+//! straight to the bytes.
+//!
+
+use std::cell::Cell;
+use std::sync::atomic::Ordering;
+
+use rkyv::api::high::{HighDeserializer, HighSerializer, HighValidator};
+use rkyv::bytecheck::CheckBytes;
+use rkyv::rancor;
+use rkyv::ser::allocator::ArenaHandle;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+
+use super::unix::UnixSharedResource;
+use crate::error::Error;
+use crate::fingerprint::TypeFingerprint;
+use crate::options::{LockTimeout, OpenMode, ResourceOptions};
+use crate::outcome::CloseOutcome;
+
+/// Types that can be stored in a `SharedArchive`: archived with rkyv, checked when read in
+/// place, and deserialized when mutated.
+///
+pub trait Archivable:
+    Sized
+    + Archive<
+        Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+                      + Deserialize<Self, HighDeserializer<rancor::Error>>,
+    > + for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>
+{
+}
+
+impl<T> Archivable for T where
+    T: Archive<
+            Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+                          + Deserialize<T, HighDeserializer<rancor::Error>>,
+        > + for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>
+{
+}
+
+pub struct UnixSharedArchive<T: Archivable> {
+    resource: UnixSharedResource<T>,
+    /// generation and version of the segment when this handle last checked the archive
+    checked: Cell<Option<(u64, u64)>>,
+}
+
+impl<T: Archivable> UnixSharedArchive<T> {
+    /// Open the resource according to `mode`, configured by `options`, archiving the value
+    /// of a new resource.
+    ///
+    pub fn open<I: FnOnce() -> T>(
+        name: &str,
+        mode: OpenMode<I>,
+        options: &ResourceOptions,
+    ) -> Result<UnixSharedArchive<T>, Error> {
+        let mode = mode.map(|init| {
+            move || -> Result<Vec<u8>, Error> {
+                return Ok(rkyv::to_bytes::<rancor::Error>(&init())?.to_vec());
+            }
+        });
+
+        return Ok(UnixSharedArchive {
+            resource: UnixSharedResource::open_raw(
+                name,
+                mode,
+                options,
+                &TypeFingerprint::of_layout::<Archived<T>>(),
+                None,
+            )?,
+            checked: Cell::new(None),
+        });
+    }
+
+    /// Access the archived value in place, under the read lock.
+    ///
+    /// #### Returns
+    /// On success, returns the value returned by `accessor`. If the bytes are not a valid
+    /// archive of `T`, returns `Error::ArchiveError`. On failure, returns an `Error`.
+    ///
+    pub fn access_timeout<F: FnOnce(&Archived<T>) -> R, R>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<R, Error> {
+        let held = self.resource.hold_read_lock(timeout)?;
+
+        let res = self
+            .resource
+            .memory()
+            .with_bytes(|bytes| -> Result<R, Error> {
+                return Ok(accessor(self.archived(bytes)?));
+            })
+            .and_then(|res| res);
+
+        held.unlock()?;
+        return res;
+    }
+
+    /// Access a deserialized copy of the value under the write lock, archiving it again
+    /// once `accessor` returns.
+    ///
+    /// #### Returns
+    /// On success, returns the value returned by `accessor`. If the bytes are not a valid
+    /// archive of `T`, returns `Error::ArchiveError`. On failure, returns an `Error`.
+    ///
+    pub fn access_mut_timeout<F: FnOnce(&mut T) -> D, D>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<D, Error> {
+        let held = self.resource.hold_write_lock(timeout)?;
+
+        let res = self
+            .resource
+            .memory()
+            .with_bytes(|bytes| -> Result<T, Error> {
+                return Ok(rkyv::deserialize::<T, rancor::Error>(
+                    self.archived(bytes)?,
+                )?);
+            })
+            .and_then(|value| value)
+            .and_then(|mut value: T| {
+                let res: D = accessor(&mut value);
+                let bytes = rkyv::to_bytes::<rancor::Error>(&value)?;
+                self.resource.memory().set_bytes(&bytes)?;
+                Ok(res)
+            });
+
+        held.unlock()?;
+        return res;
+    }
+
+    /// View `bytes` as an archive of `T`, checking them and their checksum unless this
+    /// handle already checked the same generation and version of the segment.
+    ///
+    fn archived<'a>(&self, bytes: &'a [u8]) -> Result<&'a Archived<T>, Error> {
+        let header = self.resource.memory().header();
+        let current = (
+            header.generation.load(Ordering::Acquire),
+            header.version.load(Ordering::Acquire),
+        );
+
+        if self.checked.get() == Some(current) {
+            return Ok(unsafe { rkyv::access_unchecked::<Archived<T>>(bytes) });
+        }
+
+        self.resource.memory().verify_checksum(bytes)?;
+        let archived = rkyv::access::<Archived<T>, rancor::Error>(bytes)?;
+        self.checked.set(Some(current));
+        return Ok(archived);
+    }
+
+    pub fn resource(&self) -> &UnixSharedResource<T> {
+        return &self.resource;
+    }
+
+    pub fn resource_mut(&mut self) -> &mut UnixSharedResource<T> {
+        return &mut self.resource;
+    }
+
+    pub fn close(self) -> Result<CloseOutcome, Error> {
+        return self.resource.close();
+    }
+
+    pub fn destroy(self) -> Result<CloseOutcome, Error> {
+        return self.resource.destroy();
+    }
+}
@@ -0,0 +1,224 @@
+//! ## Access Guards
+//!
+//! Guards holding the lock of a shared resource, along with a copy of its value, for as
+//! long as they live.
+//!
+
+use std::cell::Ref;
+use std::ops::{Deref, DerefMut};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
+
+use super::unix::UnixSharedResource;
+use crate::error::Error;
+
+/// Read access to a shared resource, holding its read lock until dropped, unless the
+/// resource was read without the lock in seqlock mode.
+///
+/// Derefs to the value of the resource when the lock was taken.
+///
+pub struct ReadGuard<'a, T: Serialize + DeserializeOwned> {
+    resource: &'a UnixSharedResource<T>,
+    value: ReadValue<'a, T>,
+    is_unlocked: bool,
+}
+
+/// Value behind a `ReadGuard`.
+///
+pub(crate) enum ReadValue<'a, T> {
+    /// borrowed from the copy cached by the handle
+    Cached(Ref<'a, T>),
+    /// deserialized for this guard only
+    Owned(T),
+}
+
+/// Write access to a shared resource, holding its write lock until dropped.
+///
+/// Derefs to a copy of the value of the resource, which is written back when the guard is
+/// dropped or committed, and thrown away when the guard is aborted. When the guard is
+/// dropped by a panic, the value, possibly half written, is thrown away too, and the
+/// resource is poisoned unless configured otherwise. After a writer died holding the lock,
+/// the value is only written back once the guard is marked consistent.
+///
+pub struct WriteGuard<'a, T: Serialize + DeserializeOwned> {
+    resource: &'a UnixSharedResource<T>,
+    /// `None` once written back or thrown away
+    value: Option<T>,
+    is_unlocked: bool,
+}
+
+impl<'a, T: Serialize + DeserializeOwned> ReadGuard<'a, T> {
+    /// Wrap the value read by a process holding the read lock of `resource`.
+    ///
+    pub(crate) fn new(
+        resource: &'a UnixSharedResource<T>,
+        value: ReadValue<'a, T>,
+    ) -> ReadGuard<'a, T> {
+        return ReadGuard {
+            resource,
+            value,
+            is_unlocked: false,
+        };
+    }
+
+    /// Wrap the value read by a process without taking the read lock of `resource`.
+    ///
+    pub(crate) fn unlocked(
+        resource: &'a UnixSharedResource<T>,
+        value: ReadValue<'a, T>,
+    ) -> ReadGuard<'a, T> {
+        return ReadGuard {
+            resource,
+            value,
+            is_unlocked: true,
+        };
+    }
+
+    /// Unlock the resource.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns the `Error` that dropping the guard
+    /// would only log.
+    ///
+    pub(crate) fn unlock(mut self) -> Result<(), Error> {
+        if self.is_unlocked {
+            return Ok(());
+        }
+        self.is_unlocked = true;
+        return self.resource.read_unlock();
+    }
+}
+
+impl<'a, T: Serialize + DeserializeOwned> WriteGuard<'a, T> {
+    /// Wrap the value read by a process holding the write lock of `resource`.
+    ///
+    pub(crate) fn new(resource: &'a UnixSharedResource<T>, value: T) -> WriteGuard<'a, T> {
+        return WriteGuard {
+            resource,
+            value: Some(value),
+            is_unlocked: false,
+        };
+    }
+
+    /// The writer that died holding the lock, if no process marked the value consistent
+    /// since.
+    ///
+    /// The value is then the last one a writer committed, but the dead writer may have left
+    /// other state out of step with it. Until a guard is marked consistent, writing through
+    /// a closure or committing a guard fails with `Error::OwnerDied`.
+    ///
+    pub fn owner_died(&self) -> Option<u32> {
+        return self.resource.owner_died();
+    }
+
+    /// Mark the value as consistent again after a writer died holding the lock, once it was
+    /// checked or repaired through this guard.
+    ///
+    pub fn mark_consistent(&mut self) {
+        self.resource.clear_owner_died();
+    }
+
+    /// Write the value back to the resource and unlock it.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If a writer died holding the lock and the guard was not
+    /// marked consistent, returns `Error::OwnerDied` without writing the value back. On
+    /// failure, returns the first `Error`. The resource is unlocked either way.
+    ///
+    pub fn commit(mut self) -> Result<(), Error> {
+        self.is_unlocked = true;
+        let value = self.take_value();
+        let res = self
+            .resource
+            .check_owner_died()
+            .and_then(|()| self.resource.store(value));
+        return res.and(self.resource.write_unlock());
+    }
+
+    /// Unlock the resource without writing the value back, leaving the resource as it was
+    /// when the lock was taken.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn abort(mut self) -> Result<(), Error> {
+        self.is_unlocked = true;
+        return self.resource.write_unlock();
+    }
+
+    fn take_value(&mut self) -> T {
+        return self
+            .value
+            .take()
+            .expect("the value is only taken when the guard unlocks the resource");
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match &self.value {
+            ReadValue::Cached(value) => return value,
+            ReadValue::Owned(value) => return value,
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        return self
+            .value
+            .as_ref()
+            .expect("the guard holds a value until dropped");
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        return self
+            .value
+            .as_mut()
+            .expect("the guard holds a value until dropped");
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.is_unlocked {
+            return;
+        }
+
+        if let Err(err) = self.resource.read_unlock() {
+            error!("failed to unlock shared resource in drop: {}", err);
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.is_unlocked {
+            return;
+        }
+
+        if std::thread::panicking() {
+            self.resource.poison();
+            if let Err(err) = self.resource.write_unlock() {
+                error!("failed to unlock shared resource after a panic: {}", err);
+            }
+            return;
+        }
+
+        let value = self.take_value();
+        let res = self
+            .resource
+            .check_owner_died()
+            .and_then(|()| self.resource.store(value));
+        if let Err(err) = res.and(self.resource.write_unlock()) {
+            error!("failed to commit shared resource in drop: {}", err);
+        }
+    }
+}
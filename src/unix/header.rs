@@ -0,0 +1,527 @@
+//! ## Segment Header
+//!
+//! Layout of the header placed at the start of every shared memory segment.
+//!
+//! The header only stores sizes and offsets relative to the start of the segment, never
+//! pointers, so any process that maps the segment can read it and check that it was written
+//! by a compatible version of this library. The data section starts on a page boundary so
+//! that the header and the data can be mapped separately.
+//!
+//! The data section holds two payload slots of `capacity` bytes each. Writers fill the
+//! slot that does not hold the current value, then flip `active_slot`, so the current value
+//! is complete even if a writer dies halfway through. `sequence` is odd while a writer
+//! switches slots or resizes the segment, so that readers copying the value without the
+//! lock can tell whether they copied a complete one.
+//!
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use super::process::{is_alive, start_time};
+use crate::fingerprint::TypeFingerprint;
+
+/// Identifies a segment created by this library.
+pub const SEGMENT_MAGIC: u64 = u64::from_le_bytes(*b"SHRESIPC");
+
+/// Incremented whenever the layout of `SegmentHeader` changes.
+pub const LAYOUT_VERSION: u32 = 13;
+
+/// Number of bytes of the type name kept in the header, longer names are truncated.
+pub const TYPE_NAME_LEN: usize = 128;
+
+/// Number of processes that can be tracked as holding the read lock at the same time.
+pub const READER_SLOTS: usize = 64;
+
+/// Number of handles, across every process, that can be attached to a resource at the same
+/// time.
+pub const MEMBER_SLOTS: usize = 128;
+
+/// Header placed at the start of the shared memory segment.
+///
+#[repr(C)]
+pub struct SegmentHeader {
+    /// `SEGMENT_MAGIC` once the creator finished initializing the segment
+    pub magic: AtomicU64,
+    /// always `LAYOUT_VERSION` of the process that created the segment
+    pub layout_version: u32,
+    /// size of the header in bytes
+    pub header_size: u32,
+    /// offset of the serialized value from the start of the segment
+    pub data_offset: u64,
+    /// `LockKind::id` of the lock guarding the resource
+    pub lock_kind: u32,
+    /// `Codec::id` of the format of the serialized value
+    pub codec: u32,
+    /// schema version of the value, raised when a process migrates the value
+    pub schema_version: AtomicU32,
+    /// `TypeFingerprint::hash` of the type of the value
+    pub type_hash: AtomicU64,
+    /// `TypeFingerprint::name` of the type of the value, padded with zeros
+    pub type_name: UnsafeCell<[u8; TYPE_NAME_LEN]>,
+    /// set by the final process before it unlinks the segment
+    pub closed: AtomicU32,
+    /// process that panicked while writing the value, `0` when the value is sound
+    pub poisoned: AtomicU32,
+    /// number of bytes available for the serialized value in each slot, a whole number of
+    /// pages
+    pub capacity: AtomicU64,
+    /// index of the slot holding the current value
+    pub active_slot: AtomicU32,
+    /// size and checksum of the value held by each slot
+    pub slots: [PayloadSlot; 2],
+    /// incremented every time the segment is resized
+    pub generation: AtomicU64,
+    /// incremented every time the value changes
+    pub version: AtomicU64,
+    /// odd while a writer changes the value, for readers that do not take the lock
+    pub sequence: AtomicU64,
+    /// processes holding the lock of the resource
+    pub owners: LockOwners,
+    /// handles attached to the resource
+    pub members: Members,
+    /// lock of the resource when it is a `LockKind::RobustMutex`
+    pub mutex: UnsafeCell<libc::pthread_mutex_t>,
+}
+
+/// Value held by one of the two payload slots of the segment.
+///
+#[repr(C)]
+pub struct PayloadSlot {
+    /// number of bytes used by the serialized value
+    pub size: AtomicU64,
+    /// CRC32 of the serialized value
+    pub checksum: AtomicU32,
+}
+
+/// Processes holding each part of the lock of the resource, packed with their start time
+/// like a `Member`, `0` when free.
+///
+/// A process waiting for the lock uses these to find holders that died without unlocking,
+/// without mistaking an unrelated process that was given the same PID for the holder.
+///
+#[repr(C)]
+pub struct LockOwners {
+    /// process queued for, or holding, the write lock
+    pub turnstile: AtomicU64,
+    /// process registering or unregistering as a reader
+    pub readers_mutex: AtomicU64,
+    /// process holding the write lock
+    pub writer: AtomicU64,
+    /// PID of the writer that died holding the lock, until a process marks the value
+    /// consistent
+    pub owner_died: AtomicU32,
+    /// processes holding the read lock
+    pub readers: [AtomicU64; READER_SLOTS],
+}
+
+/// Table of the handles attached to the resource, one slot per handle, so that a process
+/// holding several handles stays attached until it closed all of them.
+///
+/// Each handle is recorded with the PID and start time of its process, so that a process
+/// that was killed is told apart from an unrelated process that was given the same PID
+/// later. Handles join without the lock, so every slot is a single atomic word changed by
+/// compare-and-swap.
+///
+#[repr(C)]
+pub struct Members {
+    pub slots: [Member; MEMBER_SLOTS],
+}
+
+/// Slot of the membership table: the PID in the low 32 bits and the low 32 bits of the
+/// start time of the process in the high 32 bits, `0` when free.
+///
+#[repr(C)]
+pub struct Member {
+    pub entry: AtomicU64,
+}
+
+/// Slot taken by one handle in the membership table, and what it wrote there.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Membership {
+    slot: usize,
+    entry: u64,
+}
+
+impl Members {
+    /// Record a handle as attached, reusing the slot of a process that died if needed.
+    ///
+    /// #### Arguments
+    /// - `pid`: PID of the process holding the handle
+    /// - `start_time`: start time of the process in clock ticks since boot, `0` if unknown
+    ///
+    /// #### Returns
+    /// The slot taken by the handle, or `None` if there was no slot left.
+    ///
+    pub fn join(&self, pid: u32, start_time: u64) -> Option<Membership> {
+        let entry = Member::pack(pid, start_time);
+
+        for (index, slot) in self.slots.iter().enumerate() {
+            let current = slot.entry.load(Ordering::Acquire);
+            if Member::is_running(current) {
+                continue;
+            }
+
+            if slot
+                .entry
+                .compare_exchange(current, entry, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return Some(Membership { slot: index, entry });
+            }
+        }
+
+        return None;
+    }
+
+    /// Remove a handle from the table, along with any process that died while attached.
+    ///
+    /// A handle inherited through `fork` belongs to the parent, so a child leaving with it
+    /// leaves the slot of the parent alone.
+    ///
+    /// #### Arguments
+    /// - `membership`: slot taken by the handle when it joined
+    /// - `pid`: PID of the process leaving
+    ///
+    /// #### Returns
+    /// Whether any other handle, in this process or another one, is still attached.
+    ///
+    pub fn leave(&self, membership: Membership, pid: u32) -> bool {
+        let mut is_attached: bool = false;
+
+        for (index, slot) in self.slots.iter().enumerate() {
+            let current = slot.entry.load(Ordering::Acquire);
+            if current == 0 {
+                continue;
+            }
+
+            let is_own = index == membership.slot
+                && current == membership.entry
+                && Member::pid(current) == pid;
+            if is_own || !Member::is_running(current) {
+                // a process joining right now may have taken the slot over
+                if slot
+                    .entry
+                    .compare_exchange(current, 0, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    is_attached = true;
+                }
+            } else {
+                is_attached = true;
+            }
+        }
+
+        return is_attached;
+    }
+}
+
+impl Member {
+    /// Pack a PID and a start time into an entry.
+    ///
+    pub fn pack(pid: u32, start_time: u64) -> u64 {
+        return ((start_time & 0xFFFF_FFFF) << 32) | pid as u64;
+    }
+
+    /// The entry of this process, read once per process.
+    ///
+    pub fn current() -> u64 {
+        static CURRENT: AtomicU64 = AtomicU64::new(0);
+
+        // a child forked after the entry was read has a PID of its own
+        let pid = std::process::id();
+        let cached = CURRENT.load(Ordering::Relaxed);
+        if cached != 0 && Self::pid(cached) == pid {
+            return cached;
+        }
+
+        let entry = Self::pack(pid, start_time(pid).unwrap_or(0));
+        CURRENT.store(entry, Ordering::Relaxed);
+        return entry;
+    }
+
+    /// The PID of the process in an entry.
+    ///
+    pub fn pid(entry: u64) -> u32 {
+        return entry as u32;
+    }
+
+    /// Whether the entry holds a process that is still running.
+    ///
+    pub fn is_running(entry: u64) -> bool {
+        let pid = Self::pid(entry);
+        if pid == 0 {
+            return false;
+        }
+
+        if !is_alive(pid) {
+            return false;
+        }
+
+        // a start time of `0`, or one that cannot be read, only checks the PID
+        let recorded = entry >> 32;
+        match start_time(pid) {
+            Some(current) if recorded != 0 => return current & 0xFFFF_FFFF == recorded,
+            _ => return true,
+        }
+    }
+}
+
+impl SegmentHeader {
+    /// Size of the header in bytes.
+    pub const SIZE: usize = std::mem::size_of::<SegmentHeader>();
+
+    /// Offset of the data section: the size of the header rounded up to a whole page.
+    ///
+    pub fn data_offset() -> usize {
+        let page_size = page_size();
+        return Self::SIZE.div_ceil(page_size) * page_size;
+    }
+
+    /// Capacity of a slot that can hold `min_capacity` bytes: a whole number of pages, so
+    /// that both slots start on a page boundary.
+    ///
+    pub fn slot_capacity(min_capacity: usize) -> usize {
+        let page_size = page_size();
+        return min_capacity.max(1).div_ceil(page_size) * page_size;
+    }
+
+    /// Index of the slot holding the current value.
+    ///
+    pub fn active_index(&self) -> usize {
+        return self.active_slot.load(Ordering::Acquire) as usize & 1;
+    }
+
+    /// The slot holding the current value.
+    ///
+    pub fn active(&self) -> &PayloadSlot {
+        return &self.slots[self.active_index()];
+    }
+
+    /// Write a fresh header for a segment that can hold `capacity` bytes of data.
+    ///
+    /// The magic number is left out, so that other processes do not use the segment
+    /// before the creator calls `publish`.
+    ///
+    /// #### Safety
+    /// `ptr` must point to at least `SegmentHeader::SIZE` writable bytes.
+    ///
+    pub unsafe fn init(
+        ptr: *mut SegmentHeader,
+        capacity: u64,
+        lock_kind: u32,
+        codec: u32,
+        schema_version: u32,
+        fingerprint: &TypeFingerprint,
+    ) {
+        ptr.write(SegmentHeader {
+            magic: AtomicU64::new(0),
+            layout_version: LAYOUT_VERSION,
+            header_size: Self::SIZE as u32,
+            data_offset: Self::data_offset() as u64,
+            lock_kind,
+            codec,
+            schema_version: AtomicU32::new(schema_version),
+            type_hash: AtomicU64::new(fingerprint.hash),
+            type_name: UnsafeCell::new(type_name(fingerprint)),
+            closed: AtomicU32::new(0),
+            poisoned: AtomicU32::new(0),
+            capacity: AtomicU64::new(capacity),
+            active_slot: AtomicU32::new(0),
+            slots: std::array::from_fn(|_| PayloadSlot {
+                size: AtomicU64::new(0),
+                checksum: AtomicU32::new(0),
+            }),
+            generation: AtomicU64::new(0),
+            version: AtomicU64::new(0),
+            sequence: AtomicU64::new(0),
+            owners: LockOwners {
+                turnstile: AtomicU64::new(0),
+                readers_mutex: AtomicU64::new(0),
+                writer: AtomicU64::new(0),
+                owner_died: AtomicU32::new(0),
+                readers: std::array::from_fn(|_| AtomicU64::new(0)),
+            },
+            members: Members {
+                slots: std::array::from_fn(|_| Member {
+                    entry: AtomicU64::new(0),
+                }),
+            },
+            mutex: UnsafeCell::new(std::mem::zeroed()),
+        });
+    }
+
+    /// Name of the type of the value, as recorded by the last process that wrote the type.
+    ///
+    /// #### Safety
+    /// The caller must hold the lock of the resource.
+    ///
+    pub unsafe fn type_name(&self) -> String {
+        let type_name = &*self.type_name.get();
+        let len = type_name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(TYPE_NAME_LEN);
+        return String::from_utf8_lossy(&type_name[..len]).to_string();
+    }
+
+    /// Record that the value now has the given schema version and type.
+    ///
+    /// #### Safety
+    /// The caller must hold the write lock of the resource.
+    ///
+    pub unsafe fn set_type(&self, schema_version: u32, fingerprint: &TypeFingerprint) {
+        self.type_name.get().write(type_name(fingerprint));
+        self.type_hash.store(fingerprint.hash, Ordering::Release);
+        self.schema_version.store(schema_version, Ordering::Release);
+    }
+
+    /// Mark the segment as ready to be used by other processes.
+    ///
+    pub fn publish(&self) {
+        self.magic.store(SEGMENT_MAGIC, Ordering::Release);
+    }
+
+    /// Whether the creator of the segment finished initializing it.
+    ///
+    pub fn is_published(&self) -> bool {
+        return self.magic.load(Ordering::Acquire) != 0;
+    }
+
+    /// Mark the segment as about to be unlinked by the final process.
+    ///
+    pub fn close(&self) {
+        self.closed.store(1, Ordering::Release);
+    }
+
+    /// Whether the final process unlinked, or is about to unlink, the segment. Processes
+    /// that opened the segment meanwhile must open the resource again.
+    ///
+    pub fn is_closed(&self) -> bool {
+        return self.closed.load(Ordering::Acquire) != 0;
+    }
+
+    /// Mark the value as not to be trusted, because process `pid` panicked while writing it.
+    ///
+    pub fn poison(&self, pid: u32) {
+        self.poisoned.store(pid, Ordering::Release);
+    }
+
+    /// The process that poisoned the value, if any.
+    ///
+    pub fn poisoner(&self) -> Option<u32> {
+        match self.poisoned.load(Ordering::Acquire) {
+            0 => return None,
+            pid => return Some(pid),
+        }
+    }
+
+    /// Mark the value as sound again.
+    ///
+    pub fn clear_poison(&self) {
+        self.poisoned.store(0, Ordering::Release);
+    }
+
+    /// The writer that died holding the lock, if no process marked the value consistent
+    /// since.
+    ///
+    pub fn owner_died(&self) -> Option<u32> {
+        match self.owners.owner_died.load(Ordering::Acquire) {
+            0 => return None,
+            pid => return Some(pid),
+        }
+    }
+
+    /// Mark the value as consistent again after a writer died holding the lock.
+    ///
+    pub fn mark_consistent(&self) {
+        self.owners.owner_died.store(0, Ordering::Release);
+    }
+
+    /// Make the sequence even again after a writer died halfway through changing the value.
+    ///
+    /// Only a process holding the write lock may call this.
+    ///
+    pub fn reset_sequence(&self) {
+        let sequence = self.sequence.load(Ordering::Acquire);
+        if !sequence.is_multiple_of(2) {
+            self.sequence.store(sequence + 1, Ordering::Release);
+        }
+    }
+
+    /// Check that a header written by another process matches the layout used by this one.
+    ///
+    /// #### Arguments
+    /// - `segment_len`: returns the current size of the shared memory object in bytes
+    ///
+    /// The header may be checked while another process resizes the segment. Writers
+    /// truncate the object before raising the capacity, and raise the capacity before the
+    /// sizes, so the sizes, the capacity and the length are read in that order.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns the reason the header was rejected.
+    ///
+    pub fn validate<L: FnOnce() -> usize>(&self, segment_len: L) -> Result<(), String> {
+        let magic = self.magic.load(Ordering::Acquire);
+        if magic != SEGMENT_MAGIC {
+            return Err(format!("bad magic number {:#x}", magic));
+        }
+        if self.layout_version != LAYOUT_VERSION {
+            return Err(format!(
+                "layout version {} does not match expected version {}",
+                self.layout_version, LAYOUT_VERSION
+            ));
+        }
+        if self.header_size as usize != Self::SIZE {
+            return Err(format!(
+                "header size {} does not match expected size {}",
+                self.header_size,
+                Self::SIZE
+            ));
+        }
+        if (self.data_offset as usize) < Self::SIZE
+            || !(self.data_offset as usize).is_multiple_of(page_size())
+        {
+            return Err(format!(
+                "data offset {} is not a page boundary after the header",
+                self.data_offset
+            ));
+        }
+
+        let sizes = self
+            .slots
+            .each_ref()
+            .map(|slot| slot.size.load(Ordering::Acquire));
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let segment_len = segment_len();
+        if let Some(size) = sizes.into_iter().find(|size| *size > capacity) {
+            return Err(format!("size {} exceeds capacity {}", size, capacity));
+        }
+        let end = capacity
+            .checked_mul(2)
+            .and_then(|slots| slots.checked_add(self.data_offset));
+        if end.is_none_or(|end| end > segment_len as u64) {
+            return Err(format!(
+                "capacity {} exceeds segment length {}",
+                capacity, segment_len
+            ));
+        }
+
+        return Ok(());
+    }
+}
+
+fn page_size() -> usize {
+    return unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+}
+
+/// Name of the type identified by `fingerprint`, truncated to fit the header and padded
+/// with zeros.
+///
+fn type_name(fingerprint: &TypeFingerprint) -> [u8; TYPE_NAME_LEN] {
+    let mut type_name = [0u8; TYPE_NAME_LEN];
+    let name = fingerprint.name.as_bytes();
+    let len = name.len().min(TYPE_NAME_LEN);
+    type_name[..len].copy_from_slice(&name[..len]);
+    return type_name;
+}
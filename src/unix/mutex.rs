@@ -0,0 +1,199 @@
+//! ## Robust Mutex
+//!
+//! Inter-process mutex stored inside the shared memory segment.
+//!
+//! The mutex is a process-shared, robust pthread mutex: when its holder dies, the OS hands
+//! it over to the next process that locks it instead of leaving it locked forever.
+//!
+
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use super::header::{Member, SegmentHeader};
+use crate::error::Error;
+use crate::options::LockTimeout;
+use tracing::{error, warn};
+
+/// Inter-process lock made using the robust pthread mutex in the header of a segment.
+///
+/// Readers and writers both take the mutex exclusively. Only writers are recorded as
+/// holders, so that the death of a reader is recovered without being reported.
+///
+pub struct RobustMutex {
+    name: String,
+}
+
+impl RobustMutex {
+    /// Create a handle to the mutex of the resource with the given name.
+    ///
+    pub fn new(name: &str) -> RobustMutex {
+        let name = name.trim_start_matches('/').trim_end_matches('\0');
+
+        return RobustMutex {
+            name: name.to_string(),
+        };
+    }
+
+    /// Initialize the mutex in the header of a new segment.
+    ///
+    /// #### Safety
+    /// No other process may use the segment yet.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub unsafe fn init(header: &SegmentHeader) -> Result<(), Error> {
+        use libc::{
+            pthread_mutex_init, pthread_mutexattr_destroy, pthread_mutexattr_init,
+            pthread_mutexattr_setpshared, pthread_mutexattr_setrobust, pthread_mutexattr_t,
+            PTHREAD_MUTEX_ROBUST, PTHREAD_PROCESS_SHARED,
+        };
+
+        let mut attr: pthread_mutexattr_t = std::mem::zeroed();
+        let res = pthread_mutexattr_init(&mut attr);
+        if res != 0 {
+            error!("failed to initialize mutex attributes");
+            return Err(Error::mutex_error(res));
+        }
+
+        let mut res = pthread_mutexattr_setpshared(&mut attr, PTHREAD_PROCESS_SHARED);
+        if res == 0 {
+            res = pthread_mutexattr_setrobust(&mut attr, PTHREAD_MUTEX_ROBUST);
+        }
+        if res == 0 {
+            res = pthread_mutex_init(header.mutex.get(), &attr);
+        }
+        pthread_mutexattr_destroy(&mut attr);
+
+        if res != 0 {
+            error!("failed to initialize mutex");
+            return Err(Error::mutex_error(res));
+        }
+
+        return Ok(());
+    }
+
+    /// Lock for reading.
+    ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the lock
+    /// - `header`: header of the segment holding the mutex
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the lock could not be taken in time, returns
+    /// `Error::Timeout`, or `Error::WouldBlock` when not waiting at all. On failure, returns
+    /// an `Error`.
+    ///
+    pub fn read_lock(&self, timeout: LockTimeout, header: &SegmentHeader) -> Result<(), Error> {
+        return self.lock(timeout, header);
+    }
+
+    /// Unlock after reading.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn read_unlock(&self, header: &SegmentHeader) -> Result<(), Error> {
+        return self.unlock(header);
+    }
+
+    /// Lock for writing.
+    ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the lock
+    /// - `header`: header of the segment holding the mutex
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the lock could not be taken in time, returns
+    /// `Error::Timeout`, or `Error::WouldBlock` when not waiting at all. On failure, returns
+    /// an `Error`.
+    ///
+    pub fn write_lock(&self, timeout: LockTimeout, header: &SegmentHeader) -> Result<(), Error> {
+        self.lock(timeout, header)?;
+        header
+            .owners
+            .writer
+            .store(Member::current(), Ordering::Release);
+
+        return Ok(());
+    }
+
+    /// Unlock after writing.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn write_unlock(&self, header: &SegmentHeader) -> Result<(), Error> {
+        header.owners.writer.store(0, Ordering::Release);
+
+        return self.unlock(header);
+    }
+
+    fn lock(&self, timeout: LockTimeout, header: &SegmentHeader) -> Result<(), Error> {
+        use libc::{
+            pthread_mutex_consistent, pthread_mutex_lock, pthread_mutex_timedlock,
+            pthread_mutex_trylock, EBUSY, EOWNERDEAD, ETIMEDOUT,
+        };
+
+        let mutex = header.mutex.get();
+        let start = Instant::now();
+
+        let res = unsafe {
+            match (timeout, timeout.deadline()) {
+                (LockTimeout::NoWait, _) => pthread_mutex_trylock(mutex),
+                (_, Some(deadline)) => pthread_mutex_timedlock(mutex, &deadline),
+                (_, None) => pthread_mutex_lock(mutex),
+            }
+        };
+
+        match res {
+            0 => {}
+            EOWNERDEAD => {
+                let res = unsafe { pthread_mutex_consistent(mutex) };
+                if res != 0 {
+                    error!("failed to make mutex consistent");
+                    return Err(Error::mutex_error(res));
+                }
+
+                // a reader that died left nothing to report
+                let pid = Member::pid(header.owners.writer.swap(0, Ordering::AcqRel));
+                if pid != 0 {
+                    warn!(
+                        "recovering the lock of {} from dead process {}",
+                        self.name, pid
+                    );
+                    header.owners.owner_died.store(pid, Ordering::Release);
+                }
+            }
+            EBUSY => {
+                return Err(Error::WouldBlock {
+                    name: self.name.clone(),
+                });
+            }
+            ETIMEDOUT => {
+                return Err(Error::Timeout {
+                    name: self.name.clone(),
+                    waited: start.elapsed(),
+                });
+            }
+            res => {
+                error!("failed to lock mutex");
+                return Err(Error::mutex_error(res));
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn unlock(&self, header: &SegmentHeader) -> Result<(), Error> {
+        use libc::pthread_mutex_unlock;
+
+        let res = unsafe { pthread_mutex_unlock(header.mutex.get()) };
+        if res != 0 {
+            error!("failed to unlock mutex");
+            return Err(Error::mutex_error(res));
+        }
+
+        return Ok(());
+    }
+}
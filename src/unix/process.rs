@@ -0,0 +1,44 @@
+//! ## Processes
+//!
+//! Helpers to check on the other processes attached to a shared resource.
+//!
+
+use crate::error::get_unix_errno;
+
+/// Check whether the process with the given PID is still running.
+///
+/// Zombie processes, which have exited but were not reaped by their parent yet, are not
+/// considered running.
+///
+pub fn is_alive(pid: u32) -> bool {
+    use libc::{kill, pid_t, EPERM};
+
+    if pid == 0 {
+        return false;
+    }
+
+    // the state is the first field after the command name, which is in parentheses
+    if let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        return match stat.rsplit_once(')') {
+            Some((_, rest)) => !matches!(rest.trim_start().chars().next(), Some('Z' | 'X')),
+            None => true,
+        };
+    }
+
+    // without procfs, fall back to asking whether the process can receive signals
+    let res = unsafe { kill(pid as pid_t, 0) };
+    return res == 0 || get_unix_errno() == EPERM;
+}
+
+/// Start time of the process with the given PID, in clock ticks since boot.
+///
+/// #### Returns
+/// The start time, or `None` if it cannot be read, for example without procfs.
+///
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // the start time is the 20th field after the command name, which is in parentheses
+    let (_, rest) = stat.rsplit_once(')')?;
+    return rest.split_whitespace().nth(19)?.parse().ok();
+}
@@ -3,14 +3,20 @@
 //! Wrappers around semaphores for the uses of this library
 //!
 
+use std::ffi::CString;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::header::{LockOwners, Member};
 use crate::error::{get_unix_errno, Error};
-use tracing::error;
+use crate::options::LockTimeout;
+use tracing::{error, warn};
 
 /// Inter-process mutex made using a Named Semaphore.
 ///
 pub struct MutexSemaphore {
     sem: *mut libc::sem_t,
-    name: String,
+    name: CString,
 }
 
 impl MutexSemaphore {
@@ -23,23 +29,30 @@ impl MutexSemaphore {
     /// #### Arguments
     /// - `name`: name of the mutex
     /// - `init_locked`: whether or not to initialize the mutex locked
+    /// - `permissions`: permission bits of a new mutex
     ///
     /// #### Returns
     /// On success, returns a `MutexSemaphore`. On failure, returns an `Error`.
     ///
-    pub fn new(name: &str, init_locked: bool) -> Result<MutexSemaphore, Error> {
-        use libc::{c_int, sem_open, sem_t, EEXIST, O_CREAT, O_EXCL, O_RDWR, SEM_FAILED, S_IRWXU};
+    pub fn new(name: &str, init_locked: bool, permissions: u32) -> Result<MutexSemaphore, Error> {
+        use libc::{c_int, c_uint, sem_open, sem_t, EEXIST, O_CREAT, O_EXCL, O_RDWR, SEM_FAILED};
 
         // format the name
-        let name = name.trim_start_matches("/").trim_end_matches("\0");
-        let sem_name = format!("/sem_mutex_{}", name);
-        let name = sem_name.as_ptr().cast::<i8>();
+        let name = name.trim_start_matches('/').trim_end_matches('\0');
+        let sem_name = CString::new(format!("/sem_mutex_{}", name))
+            .map_err(|_| Error::SemaphoreError(libc::EINVAL, "invalid name".to_string()))?;
+        let name = sem_name.as_ptr();
 
         let init_value: c_int = if init_locked { 0 } else { 1 };
 
         let sem_ptr: *mut sem_t = 'open_sem: {
             unsafe {
-                let mut sem_ptr = sem_open(name, O_RDWR | O_CREAT | O_EXCL, S_IRWXU, init_value);
+                let mut sem_ptr = sem_open(
+                    name,
+                    O_RDWR | O_CREAT | O_EXCL,
+                    permissions as c_uint,
+                    init_value,
+                );
 
                 if sem_ptr == SEM_FAILED {
                     // if the file already exists, we just open it normally
@@ -67,31 +80,53 @@ impl MutexSemaphore {
 
     /// Lock the mutex before entering a critical code section.
     ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the mutex
+    ///
     /// #### Returns
-    /// On success, returns nothing. On failure, returns an `Error`.
+    /// On success, returns nothing. If the mutex could not be locked in time, returns
+    /// `Error::Timeout`, or `Error::WouldBlock` when not waiting at all. On failure,
+    /// returns an `Error`.
     ///
-    pub fn lock(&self) -> Result<(), Error> {
-        use libc::{c_void, free, malloc, sem_timedwait, timespec};
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        unsafe {
-            let duration = malloc(std::mem::size_of::<timespec>()).cast::<timespec>();
-            (*duration).tv_sec = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64
-                + 5;
+    pub fn lock(&self, timeout: LockTimeout) -> Result<(), Error> {
+        use libc::{sem_timedwait, sem_trywait, sem_wait, EAGAIN, EINTR, ETIMEDOUT};
+
+        let start = Instant::now();
+        let deadline = timeout.deadline();
+
+        loop {
+            let res = unsafe {
+                match (timeout, &deadline) {
+                    (LockTimeout::NoWait, _) => sem_trywait(self.sem),
+                    (_, Some(deadline)) => sem_timedwait(self.sem, deadline),
+                    (_, None) => sem_wait(self.sem),
+                }
+            };
 
-            let res = sem_timedwait(self.sem, duration);
-            free(duration.cast::<c_void>());
+            if res == 0 {
+                return Ok(());
+            }
 
-            if res < 0 {
-                error!("failed to lock mutex");
-                return Err(Error::sem_error());
+            match get_unix_errno() {
+                // interrupted by a signal, try again
+                EINTR => continue,
+                EAGAIN => {
+                    return Err(Error::WouldBlock {
+                        name: self.name.to_string_lossy().to_string(),
+                    });
+                }
+                ETIMEDOUT => {
+                    return Err(Error::Timeout {
+                        name: self.name.to_string_lossy().to_string(),
+                        waited: start.elapsed(),
+                    });
+                }
+                _ => {
+                    error!("failed to lock mutex");
+                    return Err(Error::sem_error());
+                }
             }
         }
-
-        return Ok(());
     }
 
     /// Unlock the mutex before exiting a critical code section.
@@ -140,7 +175,7 @@ impl MutexSemaphore {
     pub fn unlink(&self) -> Result<(), Error> {
         use libc::sem_unlink;
 
-        let name = self.name.as_ptr().cast::<i8>();
+        let name = self.name.as_ptr();
 
         unsafe {
             let res = sem_unlink(name);
@@ -156,24 +191,25 @@ impl MutexSemaphore {
 
 pub struct CounterSemaphore {
     sem: *mut libc::sem_t,
-    name: String,
+    name: CString,
 }
 
 impl CounterSemaphore {
-    pub fn new(name: &str, init_value: i32) -> Result<CounterSemaphore, Error> {
-        use libc::{c_int, sem_open, sem_t, EEXIST, O_CREAT, O_EXCL, O_RDWR, SEM_FAILED, S_IRWXU};
+    pub fn new(name: &str, init_value: i32, permissions: u32) -> Result<CounterSemaphore, Error> {
+        use libc::{c_int, c_uint, sem_open, sem_t, EEXIST, O_CREAT, O_EXCL, O_RDWR, SEM_FAILED};
 
         // format the name
-        let name = name.trim_start_matches("/").trim_end_matches("\0");
-        let sem_name = format!("sem_counter_{}", name);
-        let name = sem_name.as_ptr().cast::<i8>();
+        let name = name.trim_start_matches('/').trim_end_matches('\0');
+        let sem_name = CString::new(format!("/sem_counter_{}", name))
+            .map_err(|_| Error::SemaphoreError(libc::EINVAL, "invalid name".to_string()))?;
+        let name = sem_name.as_ptr();
 
         let sem_ptr: *mut sem_t = 'open_sem: {
             unsafe {
                 let mut sem_ptr = sem_open(
                     name,
                     O_RDWR | O_CREAT | O_EXCL,
-                    S_IRWXU,
+                    permissions as c_uint,
                     init_value as c_int,
                 );
 
@@ -231,7 +267,7 @@ impl CounterSemaphore {
         unsafe {
             let res = sem_trywait(self.sem);
             if res < 0 {
-                if res == EAGAIN {
+                if get_unix_errno() == EAGAIN {
                     return Ok(());
                 } else {
                     error!("failed to decrement counter");
@@ -260,7 +296,7 @@ impl CounterSemaphore {
 
         return Ok(value);
     }
-    
+
     /// Close the mutex without destroying it.
     ///
     /// #### Returns
@@ -288,7 +324,7 @@ impl CounterSemaphore {
     pub fn unlink(&self) -> Result<(), Error> {
         use libc::sem_unlink;
 
-        let name = self.name.as_ptr().cast::<i8>();
+        let name = self.name.as_ptr();
 
         unsafe {
             let res = sem_unlink(name);
@@ -301,3 +337,491 @@ impl CounterSemaphore {
         return Ok(());
     }
 }
+
+/// Inter-process reader-writer lock made using Named Semaphores.
+///
+/// Any number of readers can hold the lock at the same time, while a writer holds it alone.
+/// The first reader to enter locks the `room` on behalf of every reader and the last one to
+/// leave unlocks it. Writers hold the `turnstile` while they wait for and hold the `room`.
+///
+/// When given the `LockOwners` of the resource, the lock records which process holds each
+/// of its parts. While waiting, it periodically looks for holders that died without
+/// unlocking and unlocks on their behalf.
+///
+pub struct RwLockSemaphore {
+    room: MutexSemaphore,
+    readers_mutex: MutexSemaphore,
+    readers: CounterSemaphore,
+    turnstile: MutexSemaphore,
+    writer_preference: bool,
+    name: String,
+}
+
+impl RwLockSemaphore {
+    /// How often a waiting process looks for holders that died.
+    const RECOVERY_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// How long a reader that does not wait for the lock still waits for the other readers
+    /// passing the turnstile or registering, which only takes them a moment, unless they are
+    /// descheduled meanwhile on a busy machine. A writer holding or queued for the lock is
+    /// never waited for.
+    const PASSAGE_WAIT: Duration = Duration::from_millis(50);
+
+    /// Create a new inter-process reader-writer lock via the Semaphore API.
+    ///
+    /// The name of the lock allows other processes to connect to it.
+    ///
+    /// With writer preference, a writer waiting for the lock stops new readers from
+    /// entering, so that a steady stream of readers cannot starve writers. Without it,
+    /// readers keep entering as long as at least one reader holds the lock.
+    ///
+    /// #### Arguments
+    /// - `name`: name of the lock
+    /// - `writer_preference`: whether waiting writers take priority over new readers
+    /// - `permissions`: permission bits of new semaphores
+    ///
+    /// #### Returns
+    /// On success, returns a `RwLockSemaphore`. On failure, returns an `Error`.
+    ///
+    pub fn new(
+        name: &str,
+        writer_preference: bool,
+        permissions: u32,
+    ) -> Result<RwLockSemaphore, Error> {
+        let name = name.trim_start_matches('/').trim_end_matches('\0');
+
+        let room = MutexSemaphore::new(&format!("{}.rw_room", name), false, permissions)?;
+        let readers_mutex =
+            MutexSemaphore::new(&format!("{}.rw_readers", name), false, permissions)?;
+        let readers = CounterSemaphore::new(&format!("{}.rw_readers", name), 0, permissions)?;
+        let turnstile = MutexSemaphore::new(&format!("{}.rw_turnstile", name), false, permissions)?;
+
+        return Ok(RwLockSemaphore {
+            room,
+            readers_mutex,
+            readers,
+            turnstile,
+            writer_preference,
+            name: name.to_string(),
+        });
+    }
+
+    /// Lock for reading, sharing the lock with other readers.
+    ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the lock
+    /// - `owners`: where to record the holders of the lock, if anywhere
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the lock could not be taken in time, returns
+    /// `Error::Timeout`, or `Error::WouldBlock` when not waiting at all. On failure, returns
+    /// an `Error`.
+    ///
+    pub fn read_lock(
+        &self,
+        timeout: LockTimeout,
+        owners: Option<&LockOwners>,
+    ) -> Result<(), Error> {
+        self.wait_for(
+            timeout,
+            |slice| self.try_read_lock(slice, owners),
+            || self.recover_owners(owners),
+        )?;
+
+        return Ok(());
+    }
+
+    /// Unlock after reading.
+    ///
+    /// #### Arguments
+    /// - `owners`: where the holders of the lock are recorded, if anywhere
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn read_unlock(&self, owners: Option<&LockOwners>) -> Result<(), Error> {
+        let start = Instant::now();
+
+        self.readers_mutex
+            .lock(LockTimeout::default())
+            .map_err(|err| self.rename_timeout(err, start))?;
+        set_owner(owners.map(|owners| &owners.readers_mutex));
+
+        if let Some(owners) = owners {
+            let entry = Member::current();
+            if let Some(slot) = owners
+                .readers
+                .iter()
+                .find(|slot| slot.load(Ordering::Acquire) == entry)
+            {
+                slot.store(0, Ordering::Release);
+            }
+        }
+
+        let res = self.leave_room();
+
+        clear_owner(owners.map(|owners| &owners.readers_mutex));
+        self.readers_mutex.unlock()?;
+
+        return res;
+    }
+
+    /// Lock for writing, excluding every other reader and writer.
+    ///
+    /// #### Arguments
+    /// - `timeout`: how long to wait for the lock
+    /// - `owners`: where to record the holders of the lock, if anywhere
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the lock could not be taken in time, returns
+    /// `Error::Timeout`, or `Error::WouldBlock` when not waiting at all. On failure, returns
+    /// an `Error`.
+    ///
+    pub fn write_lock(
+        &self,
+        timeout: LockTimeout,
+        owners: Option<&LockOwners>,
+    ) -> Result<(), Error> {
+        self.wait_for(
+            timeout,
+            |slice| self.try_write_lock(slice, owners),
+            || self.recover_owners(owners),
+        )?;
+
+        return Ok(());
+    }
+
+    /// Unlock after writing.
+    ///
+    /// #### Arguments
+    /// - `owners`: where the holders of the lock are recorded, if anywhere
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn write_unlock(&self, owners: Option<&LockOwners>) -> Result<(), Error> {
+        clear_owner(owners.map(|owners| &owners.writer));
+        self.room.unlock()?;
+        clear_owner(owners.map(|owners| &owners.turnstile));
+        self.turnstile.unlock()?;
+
+        return Ok(());
+    }
+
+    /// Unlock every part of the lock held by a process that died.
+    ///
+    /// #### Arguments
+    /// - `owners`: where the holders of the lock are recorded
+    ///
+    /// #### Returns
+    /// On success, returns whether anything was unlocked. On failure, returns an `Error`.
+    ///
+    pub fn recover(&self, owners: &LockOwners) -> Result<bool, Error> {
+        let mut recovered: bool = false;
+
+        if let Some(pid) = take_dead_owner(&owners.writer) {
+            warn!(
+                "recovering the write lock of {} from dead process {}",
+                self.name, pid
+            );
+            owners.owner_died.store(pid, Ordering::Release);
+            self.room.unlock()?;
+            recovered = true;
+        }
+
+        if let Some(pid) = take_dead_owner(&owners.turnstile) {
+            warn!(
+                "recovering the turnstile of {} from dead process {}",
+                self.name, pid
+            );
+            self.turnstile.unlock()?;
+            recovered = true;
+        }
+
+        if let Some(pid) = take_dead_owner(&owners.readers_mutex) {
+            warn!(
+                "recovering the readers mutex of {} from dead process {}",
+                self.name, pid
+            );
+            self.readers_mutex.unlock()?;
+            recovered = true;
+        }
+
+        // readers that died still count as being in the room
+        let has_dead_reader = owners.readers.iter().any(|slot| {
+            let entry = slot.load(Ordering::Acquire);
+            entry != 0 && !Member::is_running(entry)
+        });
+        if has_dead_reader && self.readers_mutex.lock(LockTimeout::NoWait).is_ok() {
+            set_owner(Some(&owners.readers_mutex));
+
+            let mut res = Ok(());
+            for slot in owners.readers.iter() {
+                if let Some(pid) = take_dead_owner(slot) {
+                    warn!(
+                        "recovering the read lock of {} from dead process {}",
+                        self.name, pid
+                    );
+                    res = res.and(self.leave_room());
+                    recovered = true;
+                }
+            }
+
+            clear_owner(Some(&owners.readers_mutex));
+            self.readers_mutex.unlock()?;
+            res?;
+        }
+
+        return Ok(recovered);
+    }
+
+    /// Close the lock without destroying it.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn close(&self) -> Result<(), Error> {
+        self.room.close()?;
+        self.readers_mutex.close()?;
+        self.readers.close()?;
+        self.turnstile.close()?;
+
+        return Ok(());
+    }
+
+    /// Destroy the lock for all other processes.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn unlink(&self) -> Result<(), Error> {
+        self.room.unlink()?;
+        self.readers_mutex.unlink()?;
+        self.readers.unlink()?;
+        self.turnstile.unlink()?;
+
+        return Ok(());
+    }
+
+    /// Make attempts at taking the lock, each waiting for part of `timeout`.
+    /// Between attempts, call `recover` to unlock the parts of the lock held by processes
+    /// that died.
+    ///
+    fn wait_for<F: Fn(LockTimeout) -> Result<(), Error>, R: Fn() -> Result<bool, Error>>(
+        &self,
+        timeout: LockTimeout,
+        attempt: F,
+        recover: R,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+
+        loop {
+            let slice = match timeout.remaining(start) {
+                LockTimeout::NoWait => LockTimeout::NoWait,
+                LockTimeout::Forever => LockTimeout::After(Self::RECOVERY_INTERVAL),
+                LockTimeout::After(duration) => {
+                    LockTimeout::After(std::cmp::min(duration, Self::RECOVERY_INTERVAL))
+                }
+            };
+
+            match attempt(slice) {
+                Err(Error::Timeout { .. }) | Err(Error::WouldBlock { .. }) => {}
+                res => return res.map_err(|err| self.rename_timeout(err, start)),
+            }
+
+            if recover()? {
+                continue;
+            }
+
+            match timeout.remaining(start) {
+                LockTimeout::NoWait => {
+                    return Err(Error::WouldBlock {
+                        name: self.name.clone(),
+                    });
+                }
+                LockTimeout::After(duration) if duration.is_zero() => {
+                    return Err(Error::Timeout {
+                        name: self.name.clone(),
+                        waited: start.elapsed(),
+                    });
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Recover the lock from dead holders, if the holders are recorded anywhere.
+    ///
+    fn recover_owners(&self, owners: Option<&LockOwners>) -> Result<bool, Error> {
+        match owners {
+            Some(owners) => self.recover(owners),
+            None => Ok(false),
+        }
+    }
+
+    /// Make a single attempt at taking the read lock.
+    ///
+    fn try_read_lock(
+        &self,
+        timeout: LockTimeout,
+        owners: Option<&LockOwners>,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+
+        // the readers mutex is only held for a moment, so not waiting for the lock still
+        // waits briefly for the other readers registering
+        let passage = match timeout {
+            LockTimeout::NoWait => LockTimeout::After(Self::PASSAGE_WAIT),
+            timeout => timeout,
+        };
+
+        // wait behind any writer that is queued for the lock
+        if self.writer_preference {
+            match timeout {
+                LockTimeout::NoWait => self.pass_turnstile_now(owners)?,
+                timeout => self.turnstile.lock(timeout)?,
+            }
+            self.turnstile.unlock()?;
+        }
+
+        self.readers_mutex.lock(passage.remaining(start))?;
+        set_owner(owners.map(|owners| &owners.readers_mutex));
+
+        let res = self.enter_room(timeout.remaining(start));
+
+        if let (Ok(()), Some(owners)) = (&res, owners) {
+            let entry = Member::current();
+            let slot = owners.readers.iter().find(|slot| {
+                slot.compare_exchange(0, entry, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            });
+            if slot.is_none() {
+                warn!("too many readers of {} to track this one", self.name);
+            }
+        }
+
+        clear_owner(owners.map(|owners| &owners.readers_mutex));
+        self.readers_mutex.unlock()?;
+
+        return res;
+    }
+
+    /// Lock the turnstile without waiting for a writer holding it, only for the other
+    /// readers passing through it. Without a record of the holders, nobody is waited for.
+    ///
+    fn pass_turnstile_now(&self, owners: Option<&LockOwners>) -> Result<(), Error> {
+        let start = Instant::now();
+        loop {
+            match self.turnstile.lock(LockTimeout::NoWait) {
+                Err(Error::WouldBlock { .. })
+                    if owners.is_some_and(|owners| !has_writer(owners))
+                        && start.elapsed() < Self::PASSAGE_WAIT =>
+                {
+                    std::thread::yield_now();
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Make a single attempt at taking the write lock.
+    ///
+    fn try_write_lock(
+        &self,
+        timeout: LockTimeout,
+        owners: Option<&LockOwners>,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+
+        self.turnstile.lock(timeout)?;
+        set_owner(owners.map(|owners| &owners.turnstile));
+
+        if let Err(err) = self.room.lock(timeout.remaining(start)) {
+            clear_owner(owners.map(|owners| &owners.turnstile));
+            self.turnstile.unlock()?;
+            return Err(err);
+        }
+        set_owner(owners.map(|owners| &owners.writer));
+
+        return Ok(());
+    }
+
+    /// Register this process as a reader, locking the room if it is the first one.
+    /// Must be called with `readers_mutex` held.
+    ///
+    fn enter_room(&self, timeout: LockTimeout) -> Result<(), Error> {
+        if self.readers.get_value()? == 0 {
+            self.room.lock(timeout)?;
+        }
+
+        self.readers.increment()?;
+
+        return Ok(());
+    }
+
+    /// Unregister this process as a reader, unlocking the room if it is the last one.
+    /// Must be called with `readers_mutex` held.
+    ///
+    fn leave_room(&self) -> Result<(), Error> {
+        self.readers.decrement()?;
+
+        if self.readers.get_value()? == 0 {
+            self.room.unlock()?;
+        }
+
+        return Ok(());
+    }
+
+    /// Report a timeout on one of the inner semaphores as a timeout on the whole lock.
+    ///
+    fn rename_timeout(&self, err: Error, start: Instant) -> Error {
+        match err {
+            Error::Timeout { .. } => Error::Timeout {
+                name: self.name.clone(),
+                waited: start.elapsed(),
+            },
+            Error::WouldBlock { .. } => Error::WouldBlock {
+                name: self.name.clone(),
+            },
+            err => err,
+        }
+    }
+}
+
+/// Record this process as the holder of part of a lock.
+///
+fn set_owner(owner: Option<&AtomicU64>) {
+    if let Some(owner) = owner {
+        owner.store(Member::current(), Ordering::Release);
+    }
+}
+
+/// Record that part of a lock is no longer held.
+///
+fn clear_owner(owner: Option<&AtomicU64>) {
+    if let Some(owner) = owner {
+        owner.store(0, Ordering::Release);
+    }
+}
+
+/// Clear the holder of part of a lock if that process died, returning its PID.
+/// Only one of the processes racing to recover the same holder gets its PID.
+///
+fn take_dead_owner(owner: &AtomicU64) -> Option<u32> {
+    let entry = owner.load(Ordering::Acquire);
+    if entry == 0 || Member::is_running(entry) {
+        return None;
+    }
+
+    return owner
+        .compare_exchange(entry, 0, Ordering::AcqRel, Ordering::Acquire)
+        .ok()
+        .map(Member::pid);
+}
+
+/// Whether a writer holds, or is queued for, the lock.
+///
+fn has_writer(owners: &LockOwners) -> bool {
+    return owners.turnstile.load(Ordering::Acquire) != 0
+        || owners.writer.load(Ordering::Acquire) != 0;
+}
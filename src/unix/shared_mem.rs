@@ -1,229 +1,1221 @@
 //! ## Shared Memory
 //!
+//! The memory segment starts with a `SegmentHeader` followed by two slots for the serialized
+//! value. A write fills the slot that does not hold the current value and then makes it the
+//! current one, so a writer dying halfway through leaves the previous value in place.
+//!
+//! Every process keeps its own mappings of the header and of the data. When a write needs
+//! more room than the current capacity, the writer truncates the object to the new size and
+//! bumps the generation stored in the header. Readers compare that generation against the
+//! one their mapping was made for and remap before touching the data.
+//!
 
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
+use std::sync::atomic::{fence, Ordering};
+use std::time::{Duration, Instant};
 
+use bytemuck::Pod;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
 use serde::{de::DeserializeOwned, Serialize};
-use tracing::error;
+use tracing::{error, warn};
 
+use super::header::{Membership, SegmentHeader};
+use super::mutex::RobustMutex;
+use super::process::start_time;
+use crate::codec::Codec;
 use crate::error::{get_unix_errno, Error};
+use crate::fingerprint::TypeFingerprint;
+use crate::options::{LockKind, OpenMode, ResourceOptions};
 
-pub struct SharedMemory<T: Serialize + DeserializeOwned> {
-    memory: *mut MemoryMeta,
+/// Copy of the serialized value taken without the lock.
+///
+pub struct Snapshot {
+    /// version of the resource the value belongs to
+    pub version: u64,
+    /// the value, or `None` if it is still at the version the caller already holds
+    pub bytes: Option<Vec<u8>>,
+    checksum: u32,
+}
+
+pub struct SharedMemory<T> {
+    header: *mut SegmentHeader,
+    data: Cell<*mut u8>,
+    data_len: Cell<usize>,
+    generation: Cell<u64>,
     fd: i32,
-    name: String,
+    name: CString,
+    created: bool,
+    /// slot of this handle in the membership table
+    membership: Membership,
+    codec: &'static dyn Codec,
+    schema_version: u32,
     _datatype: PhantomData<T>,
 }
 
-struct MemoryMeta {
-    size: u64,
-    data: *mut u8,
-}
+impl<T> SharedMemory<T> {
+    /// Smallest data section given to a new segment, so that it is never empty.
+    const MIN_CAPACITY: usize = 64;
 
-impl<T: Serialize + DeserializeOwned> SharedMemory<T> {
-    const META_SIZE: usize = std::mem::size_of::<MemoryMeta>();
+    /// Open the shared memory segment with the given name.
+    ///
+    /// Only the process that creates the segment runs the initializer carried by `mode`,
+    /// once, and keeps the serialized value in case it has to create the segment again.
+    /// Other processes wait for the creator to publish the segment, for as long as the
+    /// init timeout in `options` allows, whatever the lock timeout. A segment whose creator
+    /// died before publishing it is unlinked by the first process to notice, and opened
+    /// again.
+    ///
+    /// #### Arguments
+    /// - `name`: name of the segment
+    /// - `mode`: whether to create the segment, open it, or both, with the initializer of
+    ///   the serialized value of a new segment
+    /// - `options`: permissions and capacity of a new segment, the lock guarding the
+    ///   resource, which an existing segment must match, and how long to wait for it
+    /// - `fingerprint`: type of the value of a new segment
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedMemory`. If the mode forbids creating or opening the
+    /// segment, returns `Error::NotFound` or `Error::AlreadyExists`. If the creator does not
+    /// publish the segment in time, returns `Error::Initializing`. On failure, returns an
+    /// `Error`.
+    ///
+    pub fn open<I: FnOnce() -> Result<Vec<u8>, Error>>(
+        name: &str,
+        mode: OpenMode<&mut Initializer<I>>,
+        options: &ResourceOptions,
+        fingerprint: &TypeFingerprint,
+    ) -> Result<SharedMemory<T>, Error> {
+        // format the name
+        let name = name.trim_start_matches('/').trim_end_matches('\0');
+        let shm_name = CString::new(format!("/shm_{}", name))
+            .map_err(|_| Error::SharedMemoryError(libc::EINVAL, "invalid name".to_string()))?;
 
-    pub fn new(name: &str, initial_value: T) -> Result<SharedMemory<T>, Error> {
-        use libc::{
-            c_int, ftruncate, mmap, shm_open, EEXIST, MAP_FAILED, MAP_SHARED, O_CREAT, O_EXCL,
-            O_RDWR, PROT_READ, PROT_WRITE, S_IRWXU,
+        let (mode, mut initializer) = match mode {
+            OpenMode::CreateNew(init) => (OpenMode::CreateNew(()), Some(init)),
+            OpenMode::OpenExisting => (OpenMode::OpenExisting, None),
+            OpenMode::CreateOrOpen(init) => (OpenMode::CreateOrOpen(()), Some(init)),
         };
 
-        // format the name
-        let name = name.trim_start_matches("/").trim_end_matches("\0");
-        let shm_name = format!("shm_{}", name);
-        let name = shm_name.as_ptr().cast::<i8>();
+        loop {
+            let memory = Self::try_open(
+                shm_name.clone(),
+                &mode,
+                initializer.as_deref_mut(),
+                options,
+                fingerprint,
+            )?;
+            if let Some(memory) = memory {
+                return Ok(memory);
+            }
+        }
+    }
+
+    /// Open the shared memory segment once, as described for `open`.
+    ///
+    /// #### Returns
+    /// On success, returns a `SharedMemory`, or `None` if the segment was abandoned by its
+    /// creator or unlinked meanwhile, and must be opened again. On failure, returns an
+    /// `Error`.
+    ///
+    fn try_open<I: FnOnce() -> Result<Vec<u8>, Error>>(
+        shm_name: CString,
+        mode: &OpenMode<()>,
+        initializer: Option<&mut Initializer<I>>,
+        options: &ResourceOptions,
+        fingerprint: &TypeFingerprint,
+    ) -> Result<Option<SharedMemory<T>>, Error> {
+        use libc::{c_int, ftruncate, mode_t, shm_open, EEXIST, ENOENT, O_CREAT, O_EXCL, O_RDWR};
+
+        let lock_kind = options.lock;
+        let codec = options.codec;
+        let permissions = options.permissions as mode_t;
+        let name = shm_name.as_ptr();
 
         // open shared memory
-        let mut memory_is_new: bool = true;
+        let mut memory_is_new = initializer.is_some();
         let shm_fd: c_int = unsafe {
-            let mut shm_fd = shm_open(name, O_RDWR | O_CREAT | O_EXCL, S_IRWXU);
+            let mut shm_fd = if memory_is_new {
+                shm_open(name, O_RDWR | O_CREAT | O_EXCL, permissions)
+            } else {
+                shm_open(name, O_RDWR, permissions)
+            };
 
             if shm_fd < 0 {
-                // possibly, the memory already exists
-                if get_unix_errno() == EEXIST {
-                    shm_fd = shm_open(name, O_RDWR, S_IRWXU);
-                    if shm_fd < 0 {
-                        error!("failed to open existing shared memory");
+                match (get_unix_errno(), mode) {
+                    // possibly, the memory already exists
+                    (EEXIST, OpenMode::CreateOrOpen(_)) => {
+                        shm_fd = shm_open(name, O_RDWR, permissions);
+                        if shm_fd < 0 {
+                            // the memory may have been unlinked meanwhile
+                            if get_unix_errno() == ENOENT {
+                                return Ok(None);
+                            }
+                            error!("failed to open existing shared memory");
+                            return Err(Error::shm_error());
+                        }
+                        memory_is_new = false;
+                    }
+                    (EEXIST, OpenMode::CreateNew(_)) => {
+                        return Err(Error::AlreadyExists {
+                            name: shm_name.to_string_lossy().to_string(),
+                        });
+                    }
+                    (ENOENT, OpenMode::OpenExisting) => {
+                        return Err(Error::NotFound {
+                            name: shm_name.to_string_lossy().to_string(),
+                        });
+                    }
+                    _ => {
+                        error!("failed to create or open shared memory");
                         return Err(Error::shm_error());
                     }
-                    memory_is_new = false;
-                } else {
-                    error!("failed to create or open shared memory");
-                    return Err(Error::shm_error());
                 }
             }
 
             shm_fd
         };
 
-        if memory_is_new {
-            // truncate the memory
-            unsafe {
-                let res = ftruncate(shm_fd.clone(), Self::META_SIZE as i64);
-                if res < 0 {
-                    error!("failed to truncate shared memory");
-                    return Err(Error::shm_error());
+        // from here on, failing or panicking releases everything taken so far, and unlinks
+        // a segment this process created but did not publish
+        let mut pending = PendingSegment {
+            fd: shm_fd,
+            name: shm_name,
+            header: std::ptr::null_mut(),
+            data: std::ptr::null_mut(),
+            data_len: 0,
+            membership: None,
+            is_new: memory_is_new,
+        };
+
+        // announce this process as opening the segment, so that a final process closing the
+        // resource meanwhile waits for it to attach
+        lock_file(shm_fd, libc::LOCK_SH)?;
+
+        // the creator claims the segment until it publishes it. A process that took the
+        // claim first found the segment abandoned and is unlinking it, before this process
+        // could claim it.
+        if memory_is_new && (!claim_creation(shm_fd)? || !is_linked(shm_fd, &pending.name)?) {
+            pending.is_new = false;
+            return Ok(None);
+        }
+
+        // build and serialize the initial value up front to know how much room it needs
+        let initial_value = match initializer {
+            Some(initializer) if memory_is_new => Some(initializer.bytes()?),
+            _ => None,
+        };
+
+        // size the segment
+        let segment_len: usize = match &initial_value {
+            Some(initial_value) => {
+                let capacity = SegmentHeader::slot_capacity(
+                    initial_value
+                        .len()
+                        .max(options.initial_capacity)
+                        .max(Self::MIN_CAPACITY),
+                );
+                let segment_len = SegmentHeader::data_offset() + 2 * capacity;
+                unsafe {
+                    let res = ftruncate(shm_fd, segment_len as i64);
+                    if res < 0 {
+                        error!("failed to truncate shared memory");
+                        return Err(Error::shm_error());
+                    }
+                }
+                segment_len
+            }
+            None => {
+                // the creator may still be sizing the segment
+                let start = Instant::now();
+                loop {
+                    let segment_len = object_len(shm_fd)?;
+                    // the creator sizes the segment at once, so an empty one may have been
+                    // abandoned or still be initializing, but not a segment of any other size
+                    if segment_len > 0 {
+                        break segment_len;
+                    }
+                    if is_abandoned(shm_fd, &pending.name, || false)? {
+                        return Ok(None);
+                    }
+                    if options.init_timeout.is_expired(start) {
+                        return Err(pending.initializing(start));
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
                 }
             }
+        };
+
+        // a segment too small to hold a header cannot be mapped and validated
+        if segment_len < SegmentHeader::SIZE {
+            error!("shared memory is too small to hold a header");
+            return Err(Error::IncompatibleSegment {
+                name: pending.name.to_string_lossy().to_string(),
+                reason: format!("segment length {} is smaller than the header", segment_len),
+            });
         }
 
-        // Map the memory meta
-        let meta_ptr = unsafe {
-            let shm_ptr = mmap(
-                std::ptr::null_mut(),
-                Self::META_SIZE,
-                PROT_READ | PROT_WRITE,
-                MAP_SHARED,
-                shm_fd.clone(),
-                0,
-            );
-            if shm_ptr == MAP_FAILED {
-                error!("failed to map shared memory metadata");
-                return Err(Error::shm_error());
+        // map the header, which stays at the same address for the lifetime of this handle
+        let header = map_segment(shm_fd, SegmentHeader::SIZE, 0)?.cast::<SegmentHeader>();
+        pending.header = header;
+
+        // initialize the header, or check the header written by another process
+        match &initial_value {
+            Some(_) => unsafe {
+                let capacity = (segment_len - SegmentHeader::data_offset()) / 2;
+                SegmentHeader::init(
+                    header,
+                    capacity as u64,
+                    lock_kind.id(),
+                    codec.id(),
+                    options.schema_version,
+                    fingerprint,
+                );
+
+                match lock_kind {
+                    LockKind::RobustMutex => RobustMutex::init(&*header)?,
+                    LockKind::RwSemaphore { .. } => (),
+                }
+            },
+            _ => {
+                // the creator may still be building the value
+                let start = Instant::now();
+                let is_published = || unsafe { (*header).is_published() };
+                while !is_published() {
+                    if is_abandoned(shm_fd, &pending.name, is_published)? {
+                        return Ok(None);
+                    }
+                    if options.init_timeout.is_expired(start) {
+                        return Err(pending.initializing(start));
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+
+                let mut res = unsafe { (*header).validate(|| object_len(shm_fd).unwrap_or(0)) };
+                if res.is_ok() {
+                    let found = unsafe { (*header).lock_kind };
+                    if found != lock_kind.id() {
+                        res = Err(format!(
+                            "lock kind {} does not match expected lock {:?}",
+                            found, lock_kind
+                        ));
+                    }
+                }
+                if let Err(reason) = res {
+                    error!("rejected shared memory header: {}", reason);
+                    return Err(Error::IncompatibleSegment {
+                        name: pending.name.to_string_lossy().to_string(),
+                        reason,
+                    });
+                }
+
+                let found = unsafe { (*header).codec };
+                if found != codec.id() {
+                    error!("shared memory was written with another codec");
+                    return Err(Error::CodecMismatch {
+                        name: pending.name.to_string_lossy().to_string(),
+                        expected: codec.name().to_string(),
+                        found: crate::codec::describe(found),
+                    });
+                }
+            }
+        };
+
+        // attach before waiting for the lock, so that a final process closing the resource
+        // meanwhile leaves the segment for this one. The creator attaches before any other
+        // process can use the segment.
+        let pid = std::process::id();
+        let membership = match unsafe { (*header).members.join(pid, start_time(pid).unwrap_or(0)) }
+        {
+            Some(membership) => membership,
+            None => {
+                error!("too many handles attached to shared memory");
+                return Err(Error::TooManyProcesses {
+                    name: pending.name.to_string_lossy().to_string(),
+                    limit: super::header::MEMBER_SLOTS,
+                });
             }
+        };
+        pending.membership = Some(membership);
 
-            shm_ptr.cast::<MemoryMeta>()
+        // map the data section
+        let (data_offset, capacity, generation) = unsafe {
+            (
+                (*header).data_offset as usize,
+                (*header).capacity.load(Ordering::Acquire) as usize,
+                (*header).generation.load(Ordering::Acquire),
+            )
         };
+        let data = map_segment(shm_fd, 2 * capacity, data_offset)?;
+        pending.data = data;
+        pending.data_len = 2 * capacity;
 
-        // set the size of the actual data
-        if memory_is_new {
+        let memory = SharedMemory {
+            header,
+            data: Cell::new(data),
+            data_len: Cell::new(2 * capacity),
+            generation: Cell::new(generation),
+            fd: shm_fd,
+            name: pending.name.clone(),
+            created: memory_is_new,
+            membership,
+            codec,
+            schema_version: options.schema_version,
+            _datatype: PhantomData::<T>,
+        };
+
+        // initialize the data
+        if let Some(initial_value) = initial_value {
             unsafe {
-                (*meta_ptr).size = std::mem::size_of_val(&initial_value) as u64;
+                memory.write_data(0, initial_value);
             }
+            let slot = &memory.header().slots[0];
+            slot.size
+                .store(initial_value.len() as u64, Ordering::Release);
+            slot.checksum
+                .store(crc32fast::hash(initial_value), Ordering::Release);
+            memory.header().publish();
         }
+        pending.keep();
 
-        // map the actual data
-        let data_ptr = unsafe {
-            let shm_ptr = mmap(
-                std::ptr::null_mut(),
-                (*meta_ptr).size as usize,
-                PROT_READ | PROT_WRITE,
-                MAP_SHARED,
-                shm_fd.clone(),
-                0,
-            );
-            if shm_ptr == MAP_FAILED {
-                error!("failed to map shared memory data");
+        // published and attached, so neither the creator nor a final process needs to wait
+        // for this one any longer
+        let res = match memory_is_new {
+            true => release_creation(shm_fd),
+            false => Ok(()),
+        };
+        if let Err(err) = res.and_then(|()| memory.unlock_openers()) {
+            memory.leave();
+            memory.close()?;
+            return Err(err);
+        }
+
+        return Ok(Some(memory));
+    }
+
+    /// The header of the segment, shared with every other process.
+    ///
+    pub fn header(&self) -> &SegmentHeader {
+        return unsafe { &*self.header };
+    }
+
+    /// Detach this handle from the segment, leaving any other handle of this process
+    /// attached.
+    ///
+    /// #### Returns
+    /// Whether any other handle is still attached.
+    ///
+    pub fn leave(&self) -> bool {
+        return self
+            .header()
+            .members
+            .leave(self.membership, std::process::id());
+    }
+
+    /// Whether this process created the segment.
+    ///
+    pub fn created(&self) -> bool {
+        return self.created;
+    }
+
+    pub fn close(&self) -> Result<(), Error> {
+        use libc::{c_void, close, munmap};
+
+        unsafe {
+            // unmap the data and the header
+            let res = munmap(self.data.get().cast::<c_void>(), self.data_len.get());
+            if res < 0 {
+                error!("failed to unmap shared memory data");
                 return Err(Error::shm_error());
             }
 
-            shm_ptr.cast::<u8>()
-        };
+            let res = munmap(self.header.cast::<c_void>(), SegmentHeader::SIZE);
+            if res < 0 {
+                error!("failed to unmap shared memory header");
+                return Err(Error::shm_error());
+            }
 
-        // initialize the data
-        if memory_is_new {
-            let initial_value = bincode::serialize(&initial_value)?;
-            let raw_data = unsafe {
-                &mut *std::ptr::slice_from_raw_parts_mut(data_ptr, (*meta_ptr).size as usize)
-            };
+            let res = close(self.fd);
+            if res < 0 {
+                error!("failed to close shared memory");
+                return Err(Error::shm_error());
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Wait for the processes still opening the segment to attach, so that a process
+    /// deciding whether it is the last one attached sees them. Held until `unlock_openers`,
+    /// or until the segment is closed.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn lock_openers(&self) -> Result<(), Error> {
+        return lock_file(self.fd, libc::LOCK_EX);
+    }
+
+    /// Let processes open the segment again after `lock_openers`.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn unlock_openers(&self) -> Result<(), Error> {
+        return lock_file(self.fd, libc::LOCK_UN);
+    }
 
-            raw_data.par_iter_mut().enumerate().for_each(|(i, v)| {
-                *v = initial_value[i];
+    pub fn unlink(&self) -> Result<(), Error> {
+        use libc::shm_unlink;
+
+        unsafe {
+            let res = shm_unlink(self.name.as_ptr());
+            if res < 0 {
+                error!("failed to unlink shared memory");
+                return Err(Error::shm_error());
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn data(&self) -> *mut u8 {
+        return self.data.get();
+    }
+
+    /// Fail if another process migrated the value to a schema version this handle does not
+    /// know, so that the value is neither misread nor overwritten in the old layout.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the schema versions differ, returns
+    /// `Error::SchemaMismatch`.
+    ///
+    pub fn check_schema(&self) -> Result<(), Error> {
+        let found = self.header().schema_version.load(Ordering::Acquire);
+        if found != self.schema_version {
+            error!("shared memory holds a value of another schema version");
+            return Err(Error::SchemaMismatch {
+                name: self.name.to_string_lossy().to_string(),
+                expected: self.schema_version,
+                found,
             });
         }
+        return Ok(());
+    }
 
-        unsafe {
-            (*meta_ptr).data = data_ptr;
+    /// Run `reader` over the current serialized value, remapping the data section first if
+    /// another process resized it.
+    ///
+    /// #### Returns
+    /// On success, returns the value returned by `reader`. On failure, returns an `Error`.
+    ///
+    pub fn with_bytes<R, F: FnOnce(&[u8]) -> R>(&self, reader: F) -> Result<R, Error> {
+        self.sync_mapping()?;
+
+        let slot = self.header().active_index();
+        let size = self.header().slots[slot].size.load(Ordering::Acquire) as usize;
+        let bytes = unsafe { &*std::ptr::slice_from_raw_parts(self.slot_ptr(slot), size) };
+
+        return Ok(reader(bytes));
+    }
+
+    /// Check the serialized value against the checksum written with it, which does not match
+    /// when a writer died halfway through writing the value.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the checksum does not match, returns
+    /// `Error::Corrupted`.
+    ///
+    pub fn verify_checksum(&self, bytes: &[u8]) -> Result<(), Error> {
+        if crc32fast::hash(bytes) != self.header().active().checksum.load(Ordering::Acquire) {
+            error!("shared memory does not match its checksum");
+            return Err(Error::Corrupted {
+                name: self.name.to_string_lossy().to_string(),
+            });
         }
+        return Ok(());
+    }
 
-        return Ok(SharedMemory {
-            memory: meta_ptr,
-            name: shm_name,
-            fd: shm_fd,
-            _datatype: PhantomData::<T>,
-        });
+    /// Write a new serialized value to the segment, unless the same bytes are already there.
+    ///
+    /// #### Returns
+    /// On success, returns whether the value changed. On failure, returns an `Error`.
+    ///
+    pub fn set_bytes(&self, new_data: &[u8]) -> Result<bool, Error> {
+        // an unchanged value is neither rewritten nor given a new version
+        if self.with_bytes(|bytes| bytes == new_data)? {
+            return Ok(false);
+        }
+
+        self.begin_write();
+        let res = self.write_bytes(new_data);
+        self.end_write();
+
+        return res.map(|()| true);
     }
 
-    pub fn get(&self) -> Result<T, Error> {
-        let bytes = unsafe {
-            &*std::ptr::slice_from_raw_parts((*self.memory).data, (*self.memory).size as usize)
-        };
-        let data = bincode::deserialize::<T>(bytes)?;
+    /// Replace the value with one migrated to a new schema version and type, which readers
+    /// see at the same time as the new value.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    pub fn migrate_bytes(
+        &self,
+        new_data: &[u8],
+        schema_version: u32,
+        fingerprint: &TypeFingerprint,
+    ) -> Result<(), Error> {
+        self.begin_write();
+        let res = self.write_bytes(new_data).map(|()| unsafe {
+            self.header().set_type(schema_version, fingerprint);
+        });
+        self.end_write();
 
-        return Ok(data);
+        return res;
     }
 
-    pub fn set(&self, new_data: T) -> Result<(), Error> {
-        use libc::{c_void, mremap, MAP_FAILED, MREMAP_MAYMOVE};
+    /// Write a new serialized value to the slot not holding the current value, growing the
+    /// segment first if needed, then make it the current value.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    fn write_bytes(&self, new_data: &[u8]) -> Result<(), Error> {
+        self.sync_mapping()?;
 
-        let new_data = bincode::serialize(&new_data)?;
-        let new_size: usize = new_data.len();
+        // grow the segment if the value no longer fits
+        if new_data.len() as u64 > self.header().capacity.load(Ordering::Acquire) {
+            self.grow(new_data.len())?;
+        }
 
-        // remap if the size is different
+        // set the new data in the other slot, then make it the current value
+        let slot = 1 - self.header().active_index();
         unsafe {
-            if (*self.memory).size as usize != new_size {
-                let new_data_ptr = mremap(
-                    (*self.memory).data.cast::<c_void>(),
-                    (*self.memory).size as usize,
-                    new_size,
-                    MREMAP_MAYMOVE,
-                );
-                if new_data_ptr == MAP_FAILED {
-                    error!("failed to remap shared memory data");
-                    return Err(Error::shm_error());
-                }
+            self.write_data(slot, new_data);
+        }
+        self.activate(slot, new_data.len(), crc32fast::hash(new_data));
+
+        return Ok(());
+    }
+
+    /// Mark the value as being changed, for readers that do not take the lock. A writer that
+    /// died halfway through left the sequence odd already.
+    ///
+    fn begin_write(&self) {
+        let sequence = &self.header().sequence;
+        let current = sequence.load(Ordering::Relaxed);
+        if current.is_multiple_of(2) {
+            sequence.store(current + 1, Ordering::Relaxed);
+            fence(Ordering::Release);
+        }
+    }
+
+    /// Mark the value as complete again, for readers that do not take the lock.
+    ///
+    fn end_write(&self) {
+        self.header().sequence.fetch_add(1, Ordering::Release);
+    }
 
-                (*self.memory).data = new_data_ptr.cast::<u8>();
-                (*self.memory).size = new_size as u64;
+    /// Copy the current serialized value without taking the lock, as a seqlock reader.
+    ///
+    /// The value may be changed by a writer while it is copied, so the copy is only returned
+    /// if no writer started or finished changing it meanwhile. The value is not copied at all
+    /// if it is still at `known_version`.
+    ///
+    /// #### Returns
+    /// On success, returns the copy, or `None` if a writer got in the way. On failure,
+    /// returns an `Error`.
+    ///
+    pub fn read_optimistic(&self, known_version: Option<u64>) -> Result<Option<Snapshot>, Error> {
+        let header = self.header();
+
+        let sequence = header.sequence.load(Ordering::Acquire);
+        if !sequence.is_multiple_of(2) {
+            return Ok(None);
+        }
+
+        let version = header.version.load(Ordering::Acquire);
+        if known_version == Some(version) {
+            fence(Ordering::Acquire);
+            if header.sequence.load(Ordering::Relaxed) != sequence {
+                return Ok(None);
             }
-        };
+            return Ok(Some(Snapshot {
+                version,
+                bytes: None,
+                checksum: 0,
+            }));
+        }
 
-        // set the new data
+        // the capacity read here is the one the copy relies on, whatever the writers do
+        let capacity = header.capacity.load(Ordering::Acquire) as usize;
+        if self.data_len.get() < 2 * capacity {
+            self.sync_mapping()?;
+        }
+        if self.data_len.get() < 2 * capacity {
+            return Ok(None);
+        }
+
+        let slot = header.active_index();
+        let size = (header.slots[slot].size.load(Ordering::Acquire) as usize).min(capacity);
+        let checksum = header.slots[slot].checksum.load(Ordering::Acquire);
+        let mut bytes = vec![0u8; size];
         unsafe {
-            let raw_data = &mut *std::ptr::slice_from_raw_parts_mut((*self.memory).data, new_size);
+            std::ptr::copy_nonoverlapping(
+                self.data().add(slot * capacity),
+                bytes.as_mut_ptr(),
+                size,
+            );
+        }
+
+        fence(Ordering::Acquire);
+        if header.sequence.load(Ordering::Relaxed) != sequence {
+            return Ok(None);
+        }
+
+        return Ok(Some(Snapshot {
+            version,
+            bytes: Some(bytes),
+            checksum,
+        }));
+    }
 
-            raw_data.par_iter_mut().enumerate().for_each(|(i, v)| {
-                *v = new_data[i];
+    /// Check a copy of the value against the checksum written with it. A snapshot without
+    /// a copy has nothing to check.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the checksum does not match, returns
+    /// `Error::Corrupted`.
+    ///
+    pub fn verify_snapshot(&self, snapshot: &Snapshot) -> Result<(), Error> {
+        let bytes = match &snapshot.bytes {
+            Some(bytes) => bytes,
+            None => return Ok(()),
+        };
+        if crc32fast::hash(bytes) != snapshot.checksum {
+            error!("shared memory does not match its checksum");
+            return Err(Error::Corrupted {
+                name: self.name.to_string_lossy().to_string(),
             });
         }
+        return Ok(());
+    }
 
-        Ok(())
+    /// The start of the current value, remapped first if another process resized it.
+    ///
+    /// #### Returns
+    /// On success, returns a pointer aligned to a page boundary. On failure, returns an
+    /// `Error`.
+    ///
+    pub fn data_ptr(&self) -> Result<*mut u8, Error> {
+        self.sync_mapping()?;
+
+        return Ok(self.slot_ptr(self.header().active_index()));
     }
 
-    pub fn close(&self) -> Result<(), Error> {
-        use libc::{c_void, close, munmap};
+    /// Make `slot`, holding `size` bytes with the given checksum, the current value.
+    ///
+    fn activate(&self, slot: usize, size: usize, checksum: u32) {
+        let header = self.header();
+
+        header.slots[slot]
+            .size
+            .store(size as u64, Ordering::Release);
+        header.slots[slot]
+            .checksum
+            .store(checksum, Ordering::Release);
+        header.active_slot.store(slot as u32, Ordering::Release);
+        header.version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// The start of the given slot in this process' mapping.
+    ///
+    fn slot_ptr(&self, slot: usize) -> *mut u8 {
+        let capacity = self.header().capacity.load(Ordering::Acquire) as usize;
+        return unsafe { self.data().add(slot * capacity) };
+    }
+
+    /// Copy `bytes` to the start of the given slot.
+    ///
+    /// #### Safety
+    /// The caller must make sure the slot can hold `bytes`.
+    ///
+    unsafe fn write_data(&self, slot: usize, bytes: &[u8]) {
+        let raw_data = &mut *std::ptr::slice_from_raw_parts_mut(self.slot_ptr(slot), bytes.len());
+
+        raw_data.par_iter_mut().enumerate().for_each(|(i, v)| {
+            *v = bytes[i];
+        });
+    }
+
+    /// Remap the data section if another process resized it since this process last mapped it.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    fn sync_mapping(&self) -> Result<(), Error> {
+        let generation = self.header().generation.load(Ordering::Acquire);
+        let capacity = self.header().capacity.load(Ordering::Acquire) as usize;
+
+        if generation != self.generation.get() || self.data_len.get() < 2 * capacity {
+            self.remap(2 * capacity)?;
+            self.generation.set(generation);
+        }
+
+        return Ok(());
+    }
+
+    /// Truncate the shared memory object so that each slot can hold at least `min_capacity`
+    /// bytes of data, then publish the new capacity to the other processes.
+    ///
+    /// The second slot moves to the new end of the first one, so a current value in the
+    /// second slot is copied there before the new capacity is published.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    fn grow(&self, min_capacity: usize) -> Result<(), Error> {
+        use libc::ftruncate;
+
+        let header = self.header();
+        let capacity = header.capacity.load(Ordering::Acquire) as usize;
+        let new_capacity = SegmentHeader::slot_capacity(std::cmp::max(min_capacity, capacity * 2));
 
         unsafe {
-            // unmap the data
-            let res = munmap(
-                (*self.memory).data.cast::<c_void>(),
-                (*self.memory).size as usize,
+            let res = ftruncate(
+                self.fd,
+                (header.data_offset as usize + 2 * new_capacity) as i64,
             );
             if res < 0 {
-                error!("failed to unmap data");
+                error!("failed to truncate shared memory");
                 return Err(Error::shm_error());
             }
+        }
 
-            // unmap the memory meta
-            let res = munmap(self.memory.cast::<c_void>(), Self::META_SIZE);
-            if res < 0 {
-                error!("failed to unmap metadata");
-                return Err(Error::shm_error());
-            }
+        self.remap(2 * new_capacity)?;
 
-            let res = close(self.fd);
-            if res < 0 {
-                error!("failed to close shared memory");
-                return Err(Error::shm_error());
+        if header.active_index() == 1 {
+            let size = header.slots[1].size.load(Ordering::Acquire) as usize;
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.data().add(capacity),
+                    self.data().add(new_capacity),
+                    size,
+                );
             }
         }
 
+        header
+            .capacity
+            .store(new_capacity as u64, Ordering::Release);
+        let generation = header.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        self.generation.set(generation);
+
         return Ok(());
     }
 
-    pub fn unlink(&self) -> Result<(), Error> {
-        use libc::shm_unlink;
+    /// Replace this process' mapping of the data section with one of `new_len` bytes.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. On failure, returns an `Error`.
+    ///
+    fn remap(&self, new_len: usize) -> Result<(), Error> {
+        use libc::{c_void, mremap, MAP_FAILED, MREMAP_MAYMOVE};
+
+        let new_ptr = unsafe {
+            mremap(
+                self.data.get().cast::<c_void>(),
+                self.data_len.get(),
+                new_len,
+                MREMAP_MAYMOVE,
+            )
+        };
+        if new_ptr == MAP_FAILED {
+            error!("failed to remap shared memory");
+            return Err(Error::shm_error());
+        }
+
+        self.data.set(new_ptr.cast::<u8>());
+        self.data_len.set(new_len);
+
+        return Ok(());
+    }
+}
+
+impl<T: Pod> SharedMemory<T> {
+    /// Copy the current value without taking the lock, as a seqlock reader, without going
+    /// through a buffer.
+    ///
+    /// #### Returns
+    /// On success, returns the copy, or `None` if a writer got in the way. If the value is
+    /// not a `T` or does not match its checksum, returns `Error::Corrupted`. On failure,
+    /// returns an `Error`.
+    ///
+    pub fn read_pod_optimistic(&self) -> Result<Option<T>, Error> {
+        let header = self.header();
+
+        let sequence = header.sequence.load(Ordering::Acquire);
+        if !sequence.is_multiple_of(2) {
+            return Ok(None);
+        }
+
+        let capacity = header.capacity.load(Ordering::Acquire) as usize;
+        if self.data_len.get() < 2 * capacity {
+            self.sync_mapping()?;
+        }
+        if self.data_len.get() < 2 * capacity {
+            return Ok(None);
+        }
+
+        let slot = header.active_index();
+        let size = header.slots[slot].size.load(Ordering::Acquire) as usize;
+        let checksum = header.slots[slot].checksum.load(Ordering::Acquire);
+        // a value of another size is only reported once the sequence shows it is not torn
+        let value: Option<T> = if size == size_of::<T>() && size <= capacity {
+            Some(unsafe { std::ptr::read_unaligned(self.data().add(slot * capacity).cast()) })
+        } else {
+            None
+        };
+
+        fence(Ordering::Acquire);
+        if header.sequence.load(Ordering::Relaxed) != sequence {
+            return Ok(None);
+        }
+
+        let value = match value {
+            Some(value) => value,
+            None => {
+                error!("shared memory holds a value of another size");
+                return Err(Error::Corrupted {
+                    name: self.name.to_string_lossy().to_string(),
+                });
+            }
+        };
+        if crc32fast::hash(bytemuck::bytes_of(&value)) != checksum {
+            error!("shared memory does not match its checksum");
+            return Err(Error::Corrupted {
+                name: self.name.to_string_lossy().to_string(),
+            });
+        }
+
+        return Ok(Some(value));
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> SharedMemory<T> {
+    pub fn get(&self) -> Result<T, Error> {
+        let data = self.with_bytes(|bytes| -> Result<T, Error> {
+            self.verify_checksum(bytes)?;
+            return self.decode(bytes);
+        })??;
+
+        return Ok(data);
+    }
+
+    /// Deserialize a copy of the serialized value, unless another process migrated the
+    /// value to another schema version.
+    ///
+    /// #### Returns
+    /// On success, returns the value. On failure, returns an `Error`.
+    ///
+    pub fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
+        self.check_schema()?;
+        return self.codec.decode::<T>(bytes);
+    }
+
+    /// Write a new value to the segment, unless it serializes to the bytes already there.
+    ///
+    /// #### Returns
+    /// On success, returns whether the value changed. On failure, returns an `Error`.
+    ///
+    pub fn set(&self, new_data: &T) -> Result<bool, Error> {
+        self.check_schema()?;
+        let new_data = self.codec.encode(new_data)?;
+
+        return self.set_bytes(&new_data);
+    }
+}
+
+/// Serialized initial value of a segment, built by the initializer the first time this
+/// process creates the segment, and kept in case the segment has to be created again.
+///
+pub struct Initializer<I> {
+    init: Option<I>,
+    bytes: Option<Vec<u8>>,
+}
+
+impl<I: FnOnce() -> Result<Vec<u8>, Error>> Initializer<I> {
+    pub fn new(init: I) -> Initializer<I> {
+        return Initializer {
+            init: Some(init),
+            bytes: None,
+        };
+    }
+
+    /// The serialized initial value, built on the first call.
+    ///
+    /// #### Returns
+    /// On success, returns the serialized value. If the initializer failed or panicked
+    /// before, returns `Error::SharedMemoryError`. On failure, returns the `Error` of the
+    /// initializer.
+    ///
+    fn bytes(&mut self) -> Result<&[u8], Error> {
+        if let Some(init) = self.init.take() {
+            self.bytes = Some(init()?);
+        }
+
+        match &self.bytes {
+            Some(bytes) => return Ok(bytes),
+            None => {
+                error!("the initial value of shared memory could not be built");
+                return Err(Error::SharedMemoryError(
+                    libc::ECANCELED,
+                    "the initializer failed before".to_string(),
+                ));
+            }
+        }
+    }
+}
+
+/// What a process opening a segment took so far, released if opening fails or panics.
+///
+/// A segment created by the process is unlinked as well, since nobody else could ever
+/// initialize it.
+///
+struct PendingSegment {
+    fd: i32,
+    name: CString,
+    header: *mut SegmentHeader,
+    data: *mut u8,
+    data_len: usize,
+    membership: Option<Membership>,
+    is_new: bool,
+}
+
+impl PendingSegment {
+    /// Keep everything, now that the segment is open.
+    ///
+    fn keep(mut self) {
+        self.fd = -1;
+    }
+
+    /// The error of a process that waited since `start` for the creator to publish the
+    /// segment.
+    ///
+    fn initializing(&self, start: Instant) -> Error {
+        error!("shared memory is still being initialized by its creator");
+        return Error::Initializing {
+            name: self.name.to_string_lossy().to_string(),
+            waited: start.elapsed(),
+        };
+    }
+}
+
+impl Drop for PendingSegment {
+    fn drop(&mut self) {
+        use libc::{c_void, close, munmap, shm_unlink};
+
+        if self.fd < 0 {
+            return;
+        }
 
         unsafe {
-            let res = shm_unlink(self.name.as_ptr().cast::<i8>());
-            if res < 0 {
-                error!("failed to unlink shared memory");
+            if let Some(membership) = self.membership {
+                (*self.header).members.leave(membership, std::process::id());
+            }
+            if !self.data.is_null() {
+                munmap(self.data.cast::<c_void>(), self.data_len);
+            }
+            if !self.header.is_null() {
+                munmap(self.header.cast::<c_void>(), SegmentHeader::SIZE);
+            }
+            close(self.fd);
+            if self.is_new {
+                shm_unlink(self.name.as_ptr());
+            }
+        }
+    }
+}
+
+/// Claim a segment this process just created, until it publishes the segment. The claim is
+/// an open file description lock on the first byte of the object, released by the kernel
+/// if the creator dies.
+///
+/// #### Returns
+/// On success, returns whether the claim was taken, or `false` if another process holds
+/// it. On failure, returns an `Error`.
+///
+fn claim_creation(fd: i32) -> Result<bool, Error> {
+    return lock_first_byte(fd, libc::F_WRLCK as i16);
+}
+
+/// Give up the claim taken by `claim_creation`.
+///
+/// #### Returns
+/// On success, returns nothing. On failure, returns an `Error`.
+///
+fn release_creation(fd: i32) -> Result<(), Error> {
+    return lock_first_byte(fd, libc::F_UNLCK as i16).map(|_| ());
+}
+
+fn lock_first_byte(fd: i32, lock_type: i16) -> Result<bool, Error> {
+    use libc::{fcntl, EACCES, EAGAIN, F_OFD_SETLK, SEEK_SET};
+
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = lock_type;
+    lock.l_whence = SEEK_SET as i16;
+    lock.l_start = 0;
+    lock.l_len = 1;
+
+    let res = unsafe { fcntl(fd, F_OFD_SETLK, &lock) };
+    if res < 0 {
+        match get_unix_errno() {
+            EACCES | EAGAIN => return Ok(false),
+            _ => {
+                error!("failed to lock shared memory object");
                 return Err(Error::shm_error());
             }
         }
+    }
 
-        return Ok(());
+    return Ok(true);
+}
+
+/// Whether the segment opened as `fd` was abandoned by a creator that died before publishing
+/// it, in which case it is unlinked, so that it can be created again.
+///
+/// #### Arguments
+/// - `is_published`: whether the creator published the segment
+///
+/// #### Returns
+/// On success, returns whether the segment was abandoned, or unlinked by another process
+/// meanwhile. On failure, returns an `Error`.
+///
+fn is_abandoned<P: Fn() -> bool>(fd: i32, name: &CStr, is_published: P) -> Result<bool, Error> {
+    // the creator holds its claim until it publishes the segment
+    if !claim_creation(fd)? {
+        return Ok(false);
+    }
+
+    let res = match is_published() {
+        true => Ok(false),
+        false => is_linked(fd, name).and_then(|is_linked| {
+            if is_linked {
+                warn!("unlinking shared memory abandoned by its creator");
+                unsafe {
+                    if libc::shm_unlink(name.as_ptr()) < 0 {
+                        error!("failed to unlink shared memory");
+                        return Err(Error::shm_error());
+                    }
+                }
+            }
+            return Ok(true);
+        }),
+    };
+
+    return res.and_then(|is_abandoned| release_creation(fd).map(|()| is_abandoned));
+}
+
+/// Whether `name` still refers to the shared memory object opened as `fd`.
+///
+/// #### Returns
+/// On success, returns whether it does. On failure, returns an `Error`.
+///
+fn is_linked(fd: i32, name: &CStr) -> Result<bool, Error> {
+    use libc::{close, shm_open, ENOENT, O_RDONLY};
+
+    let linked_fd = unsafe { shm_open(name.as_ptr(), O_RDONLY, 0) };
+    if linked_fd < 0 {
+        if get_unix_errno() == ENOENT {
+            return Ok(false);
+        }
+        error!("failed to open shared memory");
+        return Err(Error::shm_error());
+    }
+
+    let res = file_id(fd).and_then(|id| file_id(linked_fd).map(|linked_id| id == linked_id));
+    unsafe {
+        close(linked_fd);
     }
+    return res;
+}
+
+/// Device and inode of the shared memory object opened as `fd`.
+///
+fn file_id(fd: i32) -> Result<(u64, u64), Error> {
+    let stat = stat(fd)?;
+    return Ok((stat.st_dev, stat.st_ino));
+}
+
+/// Current size of the shared memory object in bytes.
+///
+/// #### Returns
+/// On success, returns the size. On failure, returns an `Error`.
+///
+fn object_len(fd: i32) -> Result<usize, Error> {
+    return Ok(stat(fd)?.st_size as usize);
+}
+
+fn stat(fd: i32) -> Result<libc::stat, Error> {
+    use libc::fstat;
+
+    let stat = unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        let res = fstat(fd, &mut stat);
+        if res < 0 {
+            error!("failed to stat shared memory");
+            return Err(Error::shm_error());
+        }
+        stat
+    };
+
+    return Ok(stat);
+}
+
+/// Apply the advisory lock operation `operation` to the whole shared memory object,
+/// retrying when interrupted by a signal. The kernel releases the lock of a process that
+/// dies.
+///
+/// #### Returns
+/// On success, returns nothing. On failure, returns an `Error`.
+///
+fn lock_file(fd: i32, operation: i32) -> Result<(), Error> {
+    loop {
+        let res = unsafe { libc::flock(fd, operation) };
+        if res == 0 {
+            return Ok(());
+        }
+        if get_unix_errno() != libc::EINTR {
+            error!("failed to lock shared memory object");
+            return Err(Error::shm_error());
+        }
+    }
+}
+
+/// Map `len` bytes of the shared memory object starting at `offset`.
+///
+/// #### Returns
+/// On success, returns a pointer to the mapping. On failure, returns an `Error`.
+///
+fn map_segment(fd: i32, len: usize, offset: usize) -> Result<*mut u8, Error> {
+    use libc::{mmap, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+
+    let ptr = unsafe {
+        mmap(
+            std::ptr::null_mut(),
+            len,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED,
+            fd,
+            offset as libc::off_t,
+        )
+    };
+    if ptr == MAP_FAILED {
+        error!("failed to map shared memory");
+        return Err(Error::shm_error());
+    }
+
+    return Ok(ptr.cast::<u8>());
 }
@@ -1,109 +1,984 @@
 //! ## Unix Implementation of the Shared Resource
 //!
 
+use std::cell::{Ref, RefCell};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use bytemuck::Pod;
 use serde::{de::DeserializeOwned, Serialize};
+use tracing::{error, warn};
 
 use crate::error::Error;
+use crate::fingerprint::TypeFingerprint;
+use crate::options::{
+    CleanupPolicy, LockKind, LockTimeout, Migration, OpenMode, ReadMode, ResourceOptions,
+};
+use crate::outcome::CloseOutcome;
 use crate::SharedResourceBackend;
 
-use super::semaphore::{CounterSemaphore, MutexSemaphore};
-use super::shared_mem::SharedMemory;
+use super::guard::{ReadGuard, ReadValue, WriteGuard};
+use super::header::SegmentHeader;
+use super::mutex::RobustMutex;
+use super::semaphore::RwLockSemaphore;
+use super::shared_mem::{Initializer, SharedMemory, Snapshot};
+
+/// How long a seqlock reader keeps copying a value that writers keep changing, or that a
+/// writer that died halfway through left marked as changing, before taking the read lock.
+const OPTIMISTIC_READ_BUDGET: Duration = Duration::from_millis(1);
 
-pub struct UnixSharedResource<T: Serialize + DeserializeOwned> {
-    mutex: MutexSemaphore,
-    counter: CounterSemaphore,
+pub struct UnixSharedResource<T> {
+    name: String,
+    lock: ResourceLock,
     resource: SharedMemory<T>,
+    timeout: LockTimeout,
+    cleanup: CleanupPolicy,
+    poison_on_panic: bool,
+    read_mode: ReadMode,
+    /// last value this handle deserialized or wrote
+    cache: RefCell<Option<CachedValue<T>>>,
+    is_detached: bool,
+}
+
+/// Deserialized copy of the value of a resource, as of a version of the resource.
+///
+struct CachedValue<T> {
+    version: u64,
+    value: T,
+}
+
+/// The lock guarding a resource, as chosen by its `LockKind`.
+///
+enum ResourceLock {
+    /// named semaphores next to the segment
+    Semaphore(RwLockSemaphore),
+    /// mutex inside the segment
+    Robust(RobustMutex),
+}
+
+impl ResourceLock {
+    fn new(name: &str, options: &ResourceOptions) -> Result<ResourceLock, Error> {
+        match options.lock {
+            LockKind::RwSemaphore { writer_preference } => {
+                return Ok(Self::Semaphore(RwLockSemaphore::new(
+                    name,
+                    writer_preference,
+                    options.permissions,
+                )?));
+            }
+            LockKind::RobustMutex => return Ok(Self::Robust(RobustMutex::new(name))),
+        }
+    }
+
+    fn read_lock(&self, timeout: LockTimeout, header: &SegmentHeader) -> Result<(), Error> {
+        match self {
+            Self::Semaphore(lock) => lock.read_lock(timeout, Some(&header.owners)),
+            Self::Robust(mutex) => mutex.read_lock(timeout, header),
+        }
+    }
+
+    fn read_unlock(&self, header: &SegmentHeader) -> Result<(), Error> {
+        match self {
+            Self::Semaphore(lock) => lock.read_unlock(Some(&header.owners)),
+            Self::Robust(mutex) => mutex.read_unlock(header),
+        }
+    }
+
+    fn write_lock(&self, timeout: LockTimeout, header: &SegmentHeader) -> Result<(), Error> {
+        match self {
+            Self::Semaphore(lock) => lock.write_lock(timeout, Some(&header.owners))?,
+            Self::Robust(mutex) => mutex.write_lock(timeout, header)?,
+        }
+
+        // a writer that died halfway through changing the value left the sequence odd
+        if header.owner_died().is_some() {
+            header.reset_sequence();
+        }
+        return Ok(());
+    }
+
+    fn write_unlock(&self, header: &SegmentHeader) -> Result<(), Error> {
+        match self {
+            Self::Semaphore(lock) => lock.write_unlock(Some(&header.owners)),
+            Self::Robust(mutex) => mutex.write_unlock(header),
+        }
+    }
+
+    fn close(&self) -> Result<(), Error> {
+        match self {
+            Self::Semaphore(lock) => lock.close(),
+            Self::Robust(_) => Ok(()),
+        }
+    }
+
+    fn unlink(&self) -> Result<(), Error> {
+        match self {
+            Self::Semaphore(lock) => lock.unlink(),
+            Self::Robust(_) => Ok(()),
+        }
+    }
 }
 
 impl<T: Serialize + DeserializeOwned> UnixSharedResource<T> {
     pub fn new(name: &str, initial_value: T) -> Result<UnixSharedResource<T>, Error> {
-        let mutex = MutexSemaphore::new(name, false)?;
-        let counter = CounterSemaphore::new(name, 0)?;
-
-        // IMPORTANT THAT THE COUNTER IS INCREMENTED BEFORE EVEN LOCKING THE MUTEX
-        counter.increment()?;
-        mutex.lock()?;
+        return Self::open(
+            name,
+            OpenMode::CreateOrOpen(initial_value),
+            &ResourceOptions::default(),
+        );
+    }
 
-        // CRITICAL SECTION
-        let resource = SharedMemory::new(name, initial_value)?;
+    pub fn with_lock(
+        name: &str,
+        initial_value: T,
+        lock: LockKind,
+    ) -> Result<UnixSharedResource<T>, Error> {
+        let options = ResourceOptions {
+            lock,
+            ..ResourceOptions::default()
+        };
+        return Self::open(name, OpenMode::CreateOrOpen(initial_value), &options);
+    }
 
-        mutex.unlock()?;
+    /// Open the resource according to `mode`, configured by `options`.
+    ///
+    pub fn open(
+        name: &str,
+        mode: OpenMode<T>,
+        options: &ResourceOptions,
+    ) -> Result<UnixSharedResource<T>, Error> {
+        return Self::open_with(name, mode.map(|v| move || v), options, None);
+    }
 
-        return Ok(UnixSharedResource {
-            mutex,
-            counter,
-            resource,
+    /// Open the resource according to `mode`, configured by `options`.
+    ///
+    /// The initializer carried by `mode` runs at most once, in the process that creates the
+    /// segment, before the segment is published to other processes. `migrate` runs in the
+    /// first process that opens a value stored with an older schema version.
+    ///
+    pub fn open_with<'m, I: FnOnce() -> T>(
+        name: &str,
+        mode: OpenMode<I>,
+        options: &ResourceOptions,
+        migrate: Option<Migration<'m, T>>,
+    ) -> Result<UnixSharedResource<T>, Error>
+    where
+        T: 'm,
+    {
+        let codec = options.codec;
+        let mode = mode.map(|init| move || codec.encode(&init()));
+        let migrate = migrate.map(|migrate| -> Migration<'m, Vec<u8>> {
+            Box::new(move |from, bytes| codec.encode(&migrate(from, bytes)?))
         });
+        return Self::open_raw(
+            name,
+            mode,
+            options,
+            &TypeFingerprint::of_serde::<T>(),
+            migrate,
+        );
     }
 }
 
-impl<T: Serialize + DeserializeOwned> Drop for UnixSharedResource<T> {
-    fn drop(&mut self) {
-        self.mutex.lock().expect("failed to lock mutex in drop");
-        self.counter
-            .decrement()
-            .expect("failed to decrement counter in drop");
-
-        // check the value of the counter
-        let mut is_final_process: bool = false;
-        if self
-            .counter
-            .get_value()
-            .expect("failed to get counter value in drop")
-            == 0
-        {
-            is_final_process = true;
-        }
-
-        if is_final_process {
+impl<T> UnixSharedResource<T> {
+    /// Open the resource according to `mode`, configured by `options`, with the value of a
+    /// new resource already serialized by the initializer carried by `mode`, holding a value
+    /// of the type identified by `fingerprint`.
+    ///
+    /// A value stored with an older schema version than the one in `options` is converted
+    /// by `migrate`, which returns it serialized, under the write lock so that only one
+    /// process migrates it.
+    ///
+    /// The handle is recorded in the membership table of the segment as soon as the
+    /// segment is opened, before waiting for the lock, and a closing process waits for the
+    /// processes still opening the segment before deciding whether it is the final one. The
+    /// final process marks the segment as closed before unlinking the lock and then the
+    /// segment, so a process that raced with the final one finds the segment closed once it
+    /// holds the lock, and opens everything again.
+    ///
+    pub(super) fn open_raw<'m, I: FnOnce() -> Result<Vec<u8>, Error>>(
+        name: &str,
+        mode: OpenMode<I>,
+        options: &ResourceOptions,
+        fingerprint: &TypeFingerprint,
+        migrate: Option<Migration<'m, Vec<u8>>>,
+    ) -> Result<UnixSharedResource<T>, Error> {
+        // the initial value is built once, even if the segment has to be created again
+        let mut mode = mode.map(Initializer::new);
+        let mut migrate = migrate;
+
+        loop {
+            let resource = SharedMemory::open(name, mode.as_mut(), options, fingerprint)?;
+            let header = resource.header();
+
+            let lock = ResourceLock::new(name, options).and_then(|lock| {
+                if let Err(err) = lock.write_lock(options.timeout, header) {
+                    lock.close()?;
+                    return Err(err);
+                }
+                Ok(lock)
+            });
+            let lock = match lock {
+                Ok(lock) => lock,
+                Err(err) => {
+                    resource.leave();
+                    resource.close()?;
+                    return Err(err);
+                }
+            };
+
+            // CRITICAL SECTION
+            let is_closed = header.is_closed();
+            let res = match is_closed {
+                true => Ok(()),
+                false => Self::check_type(name, &resource, options, fingerprint, &mut migrate),
+            };
+
+            lock.write_unlock(header)?;
+
+            if let Err(err) = res {
+                resource.leave();
+                lock.close()?;
+                resource.close()?;
+                return Err(err);
+            }
+
+            if !is_closed {
+                return Ok(UnixSharedResource {
+                    name: name.to_string(),
+                    lock,
+                    resource,
+                    timeout: options.timeout,
+                    cleanup: options.cleanup,
+                    poison_on_panic: options.poison_on_panic,
+                    read_mode: options.read_mode,
+                    cache: RefCell::new(None),
+                    is_detached: false,
+                });
+            }
+
+            lock.close()?;
+            resource.close()?;
+        }
+    }
+
+    /// Check that the segment holds a value of the type identified by `fingerprint`, with
+    /// the schema version in `options`, migrating a value stored with an older schema
+    /// version. Called under the write lock.
+    ///
+    /// #### Returns
+    /// On success, returns nothing. If the value has another type, returns
+    /// `Error::TypeMismatch`. If it has a newer schema version, or an older one and there is
+    /// no migration, returns `Error::SchemaMismatch`. On failure, returns an `Error`.
+    ///
+    fn check_type(
+        name: &str,
+        resource: &SharedMemory<T>,
+        options: &ResourceOptions,
+        fingerprint: &TypeFingerprint,
+        migrate: &mut Option<Migration<'_, Vec<u8>>>,
+    ) -> Result<(), Error> {
+        let header = resource.header();
+        let found = header.schema_version.load(Ordering::Acquire);
+
+        if found == options.schema_version {
+            if header.type_hash.load(Ordering::Acquire) != fingerprint.hash {
+                error!("shared memory holds a value of another type");
+                return Err(Error::TypeMismatch {
+                    expected: fingerprint.name.to_string(),
+                    found: unsafe { header.type_name() },
+                });
+            }
+            return Ok(());
+        }
+
+        let migrate = match migrate.take() {
+            Some(migrate) if found < options.schema_version => migrate,
+            _ => {
+                error!("shared memory holds a value of another schema version");
+                return Err(Error::SchemaMismatch {
+                    name: name.to_string(),
+                    expected: options.schema_version,
+                    found,
+                });
+            }
+        };
+
+        let new_data = resource.with_bytes(|bytes| -> Result<Vec<u8>, Error> {
+            resource.verify_checksum(bytes)?;
+            return migrate(found, bytes);
+        })??;
+        return resource.migrate_bytes(&new_data, options.schema_version, fingerprint);
+    }
+
+    /// Detach this handle from the resource, destroying the resource if this handle is the
+    /// last one attached, in any process, or if `force` is set.
+    ///
+    /// Whatever fails, this process' handles to the lock and the segment are released.
+    ///
+    /// #### Returns
+    /// On success, returns whether this handle was the last one and what was unlinked.
+    /// On failure, returns the first `Error`.
+    ///
+    fn detach(&mut self, force: bool) -> Result<CloseOutcome, Error> {
+        self.is_detached = true;
+
+        let header = self.resource.header();
+
+        // without the lock, only a forced close may unlink anything
+        let is_locked = match self.lock.write_lock(self.timeout, header) {
+            Ok(()) => true,
+            Err(err) if force => {
+                warn!("destroying shared resource without its lock: {}", err);
+                false
+            }
+            Err(err) => {
+                self.resource.leave();
+                self.release()?;
+                return Err(err);
+            }
+        };
+
+        // processes still opening the segment attach before this one decides whether it is
+        // the last one, and processes that were killed while attached are left out
+        let openers = self.resource.lock_openers();
+        let mut outcome = CloseOutcome {
+            was_final: !self.resource.leave() && openers.is_ok(),
+            ..CloseOutcome::default()
+        };
+
+        let mut res: Result<(), Error> = Ok(());
+        let is_kept = self.cleanup == CleanupPolicy::Keep;
+        if ((outcome.was_final && !is_kept) || force) && !header.is_closed() {
             // FINAL PROCESS... DESTROY EVERYTHING
             tracing::debug!("FINAL {}", std::os::unix::process::parent_id());
-            self.counter
-                .close()
-                .expect("failed to close counter in drop");
-            self.counter
-                .unlink()
-                .expect("failed to unlink counter in drop");
-            self.resource
-                .close()
-                .expect("failed to close shared memory in drop");
-            self.resource
-                .unlink()
-                .expect("failed to unlink shared memory in drop");
-            self.mutex.close().expect("failed to close mutex in drop");
-            self.mutex.unlink().expect("failed to unlink mutex in drop");
+            header.close();
+
+            res = self.lock.unlink();
+            outcome.unlinked_lock = res.is_ok() && matches!(self.lock, ResourceLock::Semaphore(_));
+
+            let unlinked = self.resource.unlink();
+            outcome.unlinked_memory = unlinked.is_ok();
+            res = res.and(unlinked);
         } else {
             // NOT FINAL, SO JUST CLOSE FOR THIS PROCESS
             tracing::debug!("NOT FINAL {}", std::os::unix::process::parent_id());
-            self.counter
-                .close()
-                .expect("failed to close counter in drop");
-            self.resource
-                .close()
-                .expect("failed to close shared memory in drop");
-            self.mutex.unlock().expect("failed to unlock mutex in drop");
-            self.mutex.close().expect("failed to close mutex in drop");
+        }
+
+        if openers.is_ok() {
+            res = res.and(self.resource.unlock_openers());
+        }
+        if is_locked {
+            res = res.and(self.lock.write_unlock(header));
+        }
+        res = res.and(self.release());
+
+        return res.map(|_| outcome);
+    }
+
+    /// Release this process' handles to the lock and the segment.
+    ///
+    fn release(&self) -> Result<(), Error> {
+        let res = self.lock.close();
+        return res.and(self.resource.close());
+    }
+
+    /// Unlock the resource after reading it through a `ReadGuard`.
+    ///
+    pub(super) fn read_unlock(&self) -> Result<(), Error> {
+        return self.lock.read_unlock(self.resource.header());
+    }
+
+    /// Unlock the resource after writing it through a `WriteGuard`.
+    ///
+    pub(super) fn write_unlock(&self) -> Result<(), Error> {
+        return self.lock.write_unlock(self.resource.header());
+    }
+
+    /// Record that this process panicked while holding the write lock, if the resource
+    /// is poisoned on panic.
+    ///
+    pub(super) fn poison(&self) {
+        if self.poison_on_panic {
+            warn!("poisoning {} after a panic while writing it", self.name);
+            self.resource.header().poison(std::process::id());
+        }
+    }
+
+    /// Mark the value as consistent again after a writer died, while holding the write lock.
+    ///
+    pub(super) fn clear_owner_died(&self) {
+        self.resource.header().mark_consistent();
+    }
+
+    /// Fail with `Error::Poisoned` if a process panicked while writing the value.
+    ///
+    fn check_poison(&self) -> Result<(), Error> {
+        match self.resource.header().poisoner() {
+            Some(pid) => {
+                return Err(Error::Poisoned {
+                    name: self.name.clone(),
+                    pid,
+                });
+            }
+            None => return Ok(()),
+        }
+    }
+
+    /// Fail with `Error::OwnerDied` if a writer died holding the lock, and no process marked
+    /// the value consistent since.
+    ///
+    pub(super) fn check_owner_died(&self) -> Result<(), Error> {
+        match self.resource.header().owner_died() {
+            Some(pid) => {
+                return Err(Error::OwnerDied {
+                    name: self.name.clone(),
+                    pid,
+                });
+            }
+            None => return Ok(()),
+        }
+    }
+
+    /// Take the read lock, failing with `Error::Poisoned` if the value cannot be trusted.
+    ///
+    /// #### Returns
+    /// On success, returns the lock, released when dropped. On failure, returns an `Error`.
+    ///
+    pub(super) fn hold_read_lock(&self, timeout: LockTimeout) -> Result<HeldLock<'_, T>, Error> {
+        self.lock.read_lock(timeout, self.resource.header())?;
+        let held = HeldLock::new(self, false);
+
+        if let Err(err) = self.check_poison() {
+            held.unlock()?;
+            return Err(err);
+        }
+        return Ok(held);
+    }
+
+    /// Take the write lock, failing with `Error::Poisoned` if the value cannot be trusted,
+    /// or with `Error::OwnerDied` if it was not checked since a writer died.
+    ///
+    /// #### Returns
+    /// On success, returns the lock, released when dropped, which poisons the value if
+    /// dropped by a panic. On failure, returns an `Error`.
+    ///
+    pub(super) fn hold_write_lock(&self, timeout: LockTimeout) -> Result<HeldLock<'_, T>, Error> {
+        self.lock.write_lock(timeout, self.resource.header())?;
+        let held = HeldLock::new(self, true);
+
+        if let Err(err) = self.check_poison().and_then(|_| self.check_owner_died()) {
+            held.unlock()?;
+            return Err(err);
+        }
+        return Ok(held);
+    }
+
+    /// The shared memory segment holding the value.
+    ///
+    #[cfg(feature = "rkyv")]
+    pub(super) fn memory(&self) -> &SharedMemory<T> {
+        return &self.resource;
+    }
+
+    /// Whether a process panicked while writing the value, and nobody cleared it since.
+    ///
+    pub fn is_poisoned(&self) -> bool {
+        return self.resource.header().poisoner().is_some();
+    }
+
+    /// Mark the value as sound again after a process panicked while writing it.
+    ///
+    pub fn clear_poison(&self) -> Result<(), Error> {
+        let header = self.resource.header();
+
+        self.lock.write_lock(self.timeout, header)?;
+        header.clear_poison();
+        return self.lock.write_unlock(header);
+    }
+
+    /// The writer that died holding the lock, if no process marked the value consistent
+    /// since.
+    ///
+    pub fn owner_died(&self) -> Option<u32> {
+        return self.resource.header().owner_died();
+    }
+
+    /// Mark the value as consistent again after a writer died holding the lock.
+    ///
+    pub fn mark_consistent(&self) -> Result<(), Error> {
+        let header = self.resource.header();
+
+        self.lock.write_lock(self.timeout, header)?;
+        header.mark_consistent();
+        return self.lock.write_unlock(header);
+    }
+
+    /// How long accessing the resource waits for the lock.
+    ///
+    pub fn timeout(&self) -> LockTimeout {
+        return self.timeout;
+    }
+
+    /// Set how long accessing the resource waits for the lock.
+    ///
+    pub fn set_timeout(&mut self, timeout: LockTimeout) {
+        self.timeout = timeout;
+    }
+
+    /// Set whether this handle takes the read lock to read the resource. A seqlock read
+    /// falls back to the read lock once `OPTIMISTIC_READ_BUDGET` is spent.
+    ///
+    pub fn set_read_mode(&mut self, read_mode: ReadMode) {
+        self.read_mode = read_mode;
+    }
+
+    /// Copy the value without taking the lock, copying it again for as long as writers get
+    /// in the way, up to `OPTIMISTIC_READ_BUDGET`. The value is not copied if it is still at
+    /// `known_version`.
+    ///
+    /// #### Returns
+    /// On success, returns the copy, or `None` once the budget is spent, for the caller to
+    /// take the read lock instead. On failure, returns an `Error`.
+    ///
+    fn snapshot(&self, known_version: Option<u64>) -> Result<Option<Snapshot>, Error> {
+        return self.read_within_budget(|| self.resource.read_optimistic(known_version));
+    }
+
+    /// Run `read` until it copies the value without a writer getting in the way, up to
+    /// `OPTIMISTIC_READ_BUDGET`.
+    ///
+    /// #### Returns
+    /// On success, returns the copy, or `None` once the budget is spent. On failure,
+    /// returns an `Error`.
+    ///
+    fn read_within_budget<S, F: Fn() -> Result<Option<S>, Error>>(
+        &self,
+        read: F,
+    ) -> Result<Option<S>, Error> {
+        let start = Instant::now();
+        loop {
+            if let Some(snapshot) = read()? {
+                self.check_poison()?;
+                return Ok(Some(snapshot));
+            }
+            if start.elapsed() >= OPTIMISTIC_READ_BUDGET {
+                return Ok(None);
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Whether this handle created the resource, rather than opening an existing one.
+    ///
+    pub fn created(&self) -> bool {
+        return self.resource.created();
+    }
+
+    /// Detach this handle from the resource, destroying the resource if this handle is
+    /// the last one attached, in any process.
+    ///
+    pub fn close(mut self) -> Result<CloseOutcome, Error> {
+        return self.detach(false);
+    }
+
+    /// Detach this process from the resource and destroy the resource, even if other
+    /// processes are still attached.
+    ///
+    pub fn destroy(mut self) -> Result<CloseOutcome, Error> {
+        return self.detach(true);
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> UnixSharedResource<T> {
+    /// Write a value back to the resource, while holding the write lock, and keep it as
+    /// the cached copy of the new version.
+    ///
+    pub(super) fn store(&self, value: T) -> Result<(), Error> {
+        self.resource.set(&value)?;
+
+        let version = self.resource.header().version.load(Ordering::Acquire);
+        if let Ok(mut cache) = self.cache.try_borrow_mut() {
+            *cache = Some(CachedValue { version, value });
+        }
+        return Ok(());
+    }
+
+    /// The value of the resource, while holding the read lock, only deserialized if
+    /// another process wrote it since this handle last did.
+    ///
+    /// A `ReadGuard` of this handle that is still alive keeps the cache borrowed, in which
+    /// case an outdated value is deserialized for the new guard alone.
+    ///
+    fn read_value(&self) -> Result<ReadValue<'_, T>, Error> {
+        let version = self.resource.header().version.load(Ordering::Acquire);
+        return self.read_value_at(version, || self.resource.get());
+    }
+
+    /// The value of the resource, read without the lock if a copy can be taken in time.
+    ///
+    /// #### Returns
+    /// On success, returns the value, or `None` for the caller to take the read lock
+    /// instead. On failure, returns an `Error`.
+    ///
+    fn read_value_optimistic(&self) -> Result<Option<ReadValue<'_, T>>, Error> {
+        let known_version = match self.cache.try_borrow() {
+            Ok(cache) => cache.as_ref().map(|cached| cached.version),
+            Err(_) => None,
+        };
+        let snapshot = match self.snapshot(known_version)? {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+
+        let bytes = match snapshot.bytes {
+            Some(ref bytes) => bytes,
+            // the cache holds the current value
+            None => return Ok(self.cached_value(snapshot.version)),
+        };
+        let value = self.read_value_at(snapshot.version, || {
+            self.resource.verify_snapshot(&snapshot)?;
+            return self.resource.decode(bytes);
+        })?;
+        return Ok(Some(value));
+    }
+
+    /// The value of the resource as of `version`, taken from the cache, or deserialized
+    /// by `load` and cached.
+    ///
+    fn read_value_at<L: Fn() -> Result<T, Error>>(
+        &self,
+        version: u64,
+        load: L,
+    ) -> Result<ReadValue<'_, T>, Error> {
+        let is_current = |cache: &Option<CachedValue<T>>| matches!(cache, Some(cached) if cached.version == version);
+
+        if let Ok(mut cache) = self.cache.try_borrow_mut() {
+            if !is_current(&cache) {
+                *cache = None;
+                let value = load()?;
+                *cache = Some(CachedValue { version, value });
+            }
+        }
+
+        match self.cached_value(version) {
+            Some(value) => return Ok(value),
+            None => return Ok(ReadValue::Owned(load()?)),
+        }
+    }
+
+    /// The cached value, if it is the value of the resource as of `version`.
+    ///
+    fn cached_value(&self, version: u64) -> Option<ReadValue<'_, T>> {
+        let cache = self.cache.try_borrow().ok()?;
+        let value = Ref::filter_map(cache, |cache| match cache {
+            Some(cached) if cached.version == version => return Some(&cached.value),
+            _ => return None,
+        });
+        return value.ok().map(ReadValue::Cached);
+    }
+
+    /// The value of the resource, while holding the write lock, taken out of the cache if
+    /// it is current, since the writer may change it.
+    ///
+    fn write_value(&self) -> Result<T, Error> {
+        let version = self.resource.header().version.load(Ordering::Acquire);
+
+        let cached = match self.cache.try_borrow_mut() {
+            Ok(mut cache) => cache.take().filter(|cached| cached.version == version),
+            Err(_) => None,
+        };
+        match cached {
+            Some(cached) => return Ok(cached.value),
+            None => return self.resource.get(),
+        }
+    }
+}
+
+impl<T: Pod> UnixSharedResource<T> {
+    /// Open the resource according to `mode`, configured by `options`, storing the value
+    /// as its own bytes so that it is accessed in place.
+    ///
+    /// #### Returns
+    /// On success, returns a `UnixSharedResource`. If the resource holds a value of another
+    /// type, returns `Error::TypeMismatch`, or `Error::IncompatibleSegment` if the value does
+    /// not have the size of `T`. On failure, returns an `Error`.
+    ///
+    pub fn open_pod<I: FnOnce() -> T>(
+        name: &str,
+        mode: OpenMode<I>,
+        options: &ResourceOptions,
+    ) -> Result<UnixSharedResource<T>, Error> {
+        let mode = mode.map(|init| {
+            move || -> Result<Vec<u8>, Error> {
+                return Ok(bytemuck::bytes_of(&init()).to_vec());
+            }
+        });
+        let resource = Self::open_raw(
+            name,
+            mode,
+            options,
+            &TypeFingerprint::of_layout::<T>(),
+            None,
+        )?;
+
+        let size = resource
+            .resource
+            .header()
+            .active()
+            .size
+            .load(Ordering::Acquire) as usize;
+        if size != std::mem::size_of::<T>() {
+            error!("shared memory holds a value of another type");
+            return Err(Error::IncompatibleSegment {
+                name: resource.name.clone(),
+                reason: format!(
+                    "value size {} does not match type size {}",
+                    size,
+                    std::mem::size_of::<T>()
+                ),
+            });
+        }
+
+        return Ok(resource);
+    }
+
+    /// Access the value in place, under the read lock, without copying it. In seqlock mode,
+    /// access a copy of the value taken without the lock instead.
+    ///
+    /// #### Returns
+    /// On success, returns the value returned by `accessor`. On failure, returns an `Error`.
+    ///
+    pub fn access_pod_timeout<F: FnOnce(&T) -> R, R>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<R, Error> {
+        if self.read_mode == ReadMode::SeqLock {
+            if let Some(value) = self.read_within_budget(|| self.resource.read_pod_optimistic())? {
+                return Ok(accessor(&value));
+            }
+        }
+
+        let held = self.hold_read_lock(timeout)?;
+
+        let data = self
+            .resource
+            .with_bytes(|bytes| self.resource.verify_checksum(bytes))
+            .and_then(|res| res)
+            .and_then(|()| self.resource.data_ptr());
+        let data = match data {
+            Ok(data) => data.cast::<T>(),
+            Err(err) => {
+                held.unlock()?;
+                return Err(err);
+            }
+        };
+        let res: R = accessor(unsafe { &*data });
+
+        held.unlock()?;
+        return Ok(res);
+    }
+
+    /// Access a copy of the value under the write lock, which is written to the other slot
+    /// of the segment and becomes the current value once `accessor` returns.
+    ///
+    /// If `accessor` panics, the value is left as it was, and the resource is poisoned
+    /// unless configured otherwise.
+    ///
+    /// #### Returns
+    /// On success, returns the value returned by `accessor`. On failure, returns an `Error`.
+    ///
+    pub fn access_pod_mut_timeout<F: FnOnce(&mut T) -> D, D>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<D, Error> {
+        let held = self.hold_write_lock(timeout)?;
+
+        let data = self
+            .resource
+            .with_bytes(|bytes| self.resource.verify_checksum(bytes))
+            .and_then(|res| res)
+            .and_then(|()| self.resource.data_ptr());
+        let mut value: T = match data {
+            Ok(data) => unsafe { *data.cast::<T>() },
+            Err(err) => {
+                held.unlock()?;
+                return Err(err);
+            }
+        };
+        let res: D = accessor(&mut value);
+
+        // an unchanged value is not given a new version
+        let updated = self.resource.set_bytes(bytemuck::bytes_of(&value));
+
+        held.unlock()?;
+        updated?;
+        return Ok(res);
+    }
+}
+
+/// Lock of a resource held while a clojure accesses its value, released even if the
+/// clojure panics.
+///
+pub(super) struct HeldLock<'a, T> {
+    resource: &'a UnixSharedResource<T>,
+    is_write: bool,
+    is_unlocked: bool,
+}
+
+impl<'a, T> HeldLock<'a, T> {
+    fn new(resource: &'a UnixSharedResource<T>, is_write: bool) -> HeldLock<'a, T> {
+        return HeldLock {
+            resource,
+            is_write,
+            is_unlocked: false,
+        };
+    }
+
+    pub(super) fn unlock(mut self) -> Result<(), Error> {
+        self.is_unlocked = true;
+        if self.is_write {
+            return self.resource.write_unlock();
+        }
+        return self.resource.read_unlock();
+    }
+}
+
+impl<T> Drop for HeldLock<'_, T> {
+    fn drop(&mut self) {
+        if self.is_unlocked {
+            return;
+        }
+
+        let res = if self.is_write {
+            self.resource.poison();
+            self.resource.write_unlock()
+        } else {
+            self.resource.read_unlock()
+        };
+        if let Err(err) = res {
+            error!("failed to unlock shared resource after a panic: {}", err);
+        }
+    }
+}
+
+impl<T> Drop for UnixSharedResource<T> {
+    fn drop(&mut self) {
+        if self.is_detached {
+            return;
+        }
+
+        if let Err(err) = self.detach(false) {
+            error!("failed to close shared resource in drop: {}", err);
         }
     }
 }
 
 impl<T: Serialize + DeserializeOwned> SharedResourceBackend<T> for UnixSharedResource<T> {
-    fn access<F: Fn(&T) -> R, R>(&self, accessor: F) -> Result<R, Error> {
-        self.mutex.lock()?;
-        let data: T = self.resource.get()?;
-        let res: R = accessor(&data);
-        self.mutex.unlock()?;
+    fn access<F: FnOnce(&T) -> R, R>(&self, accessor: F) -> Result<R, Error> {
+        return self.access_timeout(self.timeout, accessor);
+    }
+
+    fn access_mut<F: FnOnce(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error> {
+        return self.access_mut_timeout(self.timeout, accessor);
+    }
+
+    fn access_timeout<F: FnOnce(&T) -> R, R>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<R, Error> {
+        let guard = self.read_timeout(timeout)?;
+        let res: R = accessor(&guard);
+        guard.unlock()?;
         return Ok(res);
     }
 
-    fn access_mut<F: Fn(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error> {
-        self.mutex.lock()?;
-        let mut data: T = self.resource.get()?;
-        let res: D = accessor(&mut data);
-        self.resource.set(data)?;
-        self.mutex.unlock()?;
+    fn access_mut_timeout<F: FnOnce(&mut T) -> D, D>(
+        &self,
+        timeout: LockTimeout,
+        accessor: F,
+    ) -> Result<D, Error> {
+        let mut guard = self.write_timeout(timeout)?;
+        if let Err(err) = self.check_owner_died() {
+            guard.abort()?;
+            return Err(err);
+        }
+        let res: D = accessor(&mut guard);
+        guard.commit()?;
         return Ok(res);
     }
+
+    fn access_mut_fallible<F: FnOnce(&mut T) -> Result<D, E>, D, E: From<Error>>(
+        &self,
+        accessor: F,
+    ) -> Result<D, E> {
+        let mut guard = self.write()?;
+        if let Err(err) = self.check_owner_died() {
+            guard.abort()?;
+            return Err(err.into());
+        }
+        match accessor(&mut guard) {
+            Ok(res) => {
+                guard.commit()?;
+                return Ok(res);
+            }
+            Err(err) => {
+                guard.abort()?;
+                return Err(err);
+            }
+        }
+    }
+
+    fn read(&self) -> Result<ReadGuard<'_, T>, Error> {
+        return self.read_timeout(self.timeout);
+    }
+
+    fn write(&self) -> Result<WriteGuard<'_, T>, Error> {
+        return self.write_timeout(self.timeout);
+    }
+
+    fn read_timeout(&self, timeout: LockTimeout) -> Result<ReadGuard<'_, T>, Error> {
+        let header = self.resource.header();
+
+        if self.read_mode == ReadMode::SeqLock {
+            if let Some(value) = self.read_value_optimistic()? {
+                return Ok(ReadGuard::unlocked(self, value));
+            }
+        }
+
+        self.lock.read_lock(timeout, header)?;
+        match self.check_poison().and_then(|_| self.read_value()) {
+            Ok(value) => return Ok(ReadGuard::new(self, value)),
+            Err(err) => {
+                self.lock.read_unlock(header)?;
+                return Err(err);
+            }
+        }
+    }
+
+    fn write_timeout(&self, timeout: LockTimeout) -> Result<WriteGuard<'_, T>, Error> {
+        let header = self.resource.header();
+
+        self.lock.write_lock(timeout, header)?;
+        match self.check_poison().and_then(|_| self.write_value()) {
+            Ok(value) => return Ok(WriteGuard::new(self, value)),
+            Err(err) => {
+                self.lock.write_unlock(header)?;
+                return Err(err);
+            }
+        }
+    }
+
+    fn try_access<F: FnOnce(&T) -> R, R>(&self, accessor: F) -> Result<R, Error> {
+        return self.access_timeout(LockTimeout::NoWait, accessor);
+    }
+
+    fn try_access_mut<F: FnOnce(&mut T) -> D, D>(&self, accessor: F) -> Result<D, Error> {
+        return self.access_mut_timeout(LockTimeout::NoWait, accessor);
+    }
 }
 
 #[cfg(test)]
@@ -139,6 +1014,25 @@ mod tests {
         }
     }
 
+    fn write_raw_segment(name: &str, bytes: &[u8]) {
+        use libc::{close, shm_open, write, O_CREAT, O_RDWR, S_IRWXU};
+
+        let shm_name = std::ffi::CString::new(format!("/shm_{}", name)).unwrap();
+        unsafe {
+            let fd = shm_open(shm_name.as_ptr(), O_RDWR | O_CREAT, S_IRWXU);
+            assert!(fd >= 0);
+            write(fd, bytes.as_ptr().cast(), bytes.len());
+            close(fd);
+        }
+    }
+
+    fn unlink_raw_segment(name: &str) {
+        let shm_name = std::ffi::CString::new(format!("/shm_{}", name)).unwrap();
+        unsafe {
+            libc::shm_unlink(shm_name.as_ptr());
+        }
+    }
+
     rusty_fork_test! {
         #[test]
         fn test_single_proc_open_close_resource() {
@@ -150,6 +1044,38 @@ mod tests {
             drop(resource);
         }
 
+        #[test]
+        fn test_single_proc_close_one_handle() {
+            use crate::options::{OpenMode, ResourceOptions};
+
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let other =
+                UnixSharedResource::<usize>::new(&name, 0).expect("failed to open resource");
+
+            // closing one handle leaves the resource to the other handle of this process
+            let closed = other.close().expect("failed to close resource");
+            resource
+                .access_mut(|data| { *data = 5; })
+                .expect("failed to access mutable data");
+            let existing =
+                UnixSharedResource::<usize>::open(&name, OpenMode::OpenExisting, &ResourceOptions::default())
+                    .expect("failed to open resource");
+            let data = existing.access(|data| *data).expect("failed to access data");
+            let closed_existing = existing.close().expect("failed to close resource");
+            let closed_last = resource.close().expect("failed to close resource");
+
+            assert!(!closed.was_final);
+            assert!(!closed.unlinked_memory);
+            assert!(!closed.unlinked_lock);
+            assert_eq!(data, 5);
+            assert!(!closed_existing.was_final);
+            assert!(closed_last.was_final);
+            assert!(closed_last.unlinked_memory);
+        }
+
         #[test]
         fn test_many_proc_open_close_resource() {
             let name = init();
@@ -170,7 +1096,7 @@ mod tests {
                 UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
 
             let data = resource
-                .access(|data| data.clone())
+                .access(|data| *data)
                 .expect("failed to access data");
 
             drop(resource);
@@ -187,7 +1113,7 @@ mod tests {
                 UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
 
             let data = resource
-                .access(|data| data.clone())
+                .access(|data| *data)
                 .expect("failed to access data");
 
             drop(resource);
@@ -207,7 +1133,7 @@ mod tests {
                 .expect("failed to access mutable data");
 
             let data = resource
-                .access_mut(|data| data.clone())
+                .access_mut(|data| *data)
                 .expect("failed to access data");
 
             drop(resource);
@@ -229,11 +1155,11 @@ mod tests {
             let val: usize = if std::process::id() == parent_id {
                 std::thread::sleep(std::time::Duration::from_millis(10));
                 resource
-                    .access_mut(|data| data.clone())
+                    .access_mut(|data| *data)
                     .expect("failed to access data")
             } else {
                 resource
-                    .access_mut(|data| { *data = 100; data.clone() })
+                    .access_mut(|data| { *data = 100; *data })
                     .expect("failed to access mutable data")
             };
 
@@ -242,6 +1168,2022 @@ mod tests {
             assert_eq!(val, 100);
         }
 
-
+        #[test]
+        fn test_many_proc_mutate_attached() {
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // the parent stays attached until after the child read the value and closed
+            let (val, is_parent) = if std::process::id() == parent_id {
+                let val = resource
+                    .access_mut(|data| { *data = 100; *data })
+                    .expect("failed to access mutable data");
+                std::thread::sleep(std::time::Duration::from_millis(150));
+                (val, true)
+            } else {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                let val = resource
+                    .access(|data| *data)
+                    .expect("failed to access data");
+                (val, false)
+            };
+
+            let outcome = resource.close().expect("failed to close resource");
+
+            assert_eq!(val, 100);
+            assert_eq!(outcome.was_final, is_parent);
+        }
+
+        #[test]
+        fn test_single_proc_grow() {
+            let name = init();
+
+            let resource = UnixSharedResource::<String>::new(&name, String::new())
+                .expect("failed to open resource");
+
+            resource
+                .access_mut(|data| { *data = "a".repeat(10_000); })
+                .expect("failed to access mutable data");
+
+            let len = resource
+                .access(|data| data.len())
+                .expect("failed to access data");
+
+            drop(resource);
+
+            assert_eq!(len, 10_000);
+        }
+
+        #[test]
+        fn test_many_proc_grow() {
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource = UnixSharedResource::<Vec<u64>>::new(&name, vec![1])
+                .expect("failed to open resource");
+
+            let val: Vec<u64> = if std::process::id() == parent_id {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                resource
+                    .access(|data| data.clone())
+                    .expect("failed to access data")
+            } else {
+                resource
+                    .access_mut(|data| { *data = (0..4096).collect(); data.clone() })
+                    .expect("failed to access mutable data")
+            };
+
+            drop(resource);
+
+            assert_eq!(val, (0..4096).collect::<Vec<u64>>());
+        }
+
+        #[test]
+        fn test_many_proc_concurrent_read() {
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // once both processes opened the resource, the child holds the read lock while the
+            // parent reads, so the read does not wait
+            let (data, elapsed) = if std::process::id() == parent_id {
+                std::thread::sleep(std::time::Duration::from_millis(150));
+                let start = std::time::Instant::now();
+                let data = resource
+                    .access(|data| *data)
+                    .expect("failed to access data");
+                (data, start.elapsed())
+            } else {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                let data = resource
+                    .access(|data| {
+                        std::thread::sleep(std::time::Duration::from_millis(300));
+                        *data
+                    })
+                    .expect("failed to access data");
+                (data, std::time::Duration::ZERO)
+            };
+
+            drop(resource);
+
+            assert_eq!(data, 1000);
+            assert!(elapsed < std::time::Duration::from_millis(150));
+        }
+
+        #[test]
+        fn test_many_proc_lock_timeout() {
+            use crate::error::Error;
+            use crate::options::LockTimeout;
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // once both processes opened the resource, the child holds the write lock while the
+            // parent tries to read
+            let (timed_out, not_waited) = if std::process::id() == parent_id {
+                std::thread::sleep(Duration::from_millis(150));
+                let timed_out = resource
+                    .access_timeout(LockTimeout::After(Duration::from_millis(50)), |data| *data);
+                let not_waited = resource.access_timeout(LockTimeout::NoWait, |data| *data);
+                (timed_out, not_waited)
+            } else {
+                std::thread::sleep(Duration::from_millis(50));
+                resource
+                    .access_mut(|_| std::thread::sleep(Duration::from_millis(300)))
+                    .expect("failed to access mutable data");
+                (Ok(0), Ok(0))
+            };
+
+            let data = resource
+                .access_timeout(LockTimeout::Forever, |data| *data)
+                .expect("failed to access data");
+
+            drop(resource);
+
+            if std::process::id() == parent_id {
+                assert!(matches!(
+                    timed_out,
+                    Err(Error::Timeout { waited, .. }) if waited >= Duration::from_millis(50)
+                ));
+                assert!(matches!(not_waited, Err(Error::WouldBlock { .. })));
+            }
+            assert_eq!(data, 1000);
+        }
+
+        #[test]
+        fn test_many_proc_try_access() {
+            use crate::error::Error;
+            use std::time::{Duration, Instant};
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // once both processes opened the resource, the child holds the write lock while the
+            // parent tries to read and write, without waiting for the child
+            let (read, write, waited) = if std::process::id() == parent_id {
+                std::thread::sleep(Duration::from_millis(150));
+                let start = Instant::now();
+                let read = resource.try_access(|data| *data);
+                let write = resource.try_access_mut(|data| *data);
+                (read, write, start.elapsed())
+            } else {
+                std::thread::sleep(Duration::from_millis(50));
+                resource
+                    .access_mut(|_| std::thread::sleep(Duration::from_millis(300)))
+                    .expect("failed to access mutable data");
+                (Ok(0), Ok(0), Duration::ZERO)
+            };
+
+            // once the lock is free, trying succeeds. The parent stays attached the longest.
+            if std::process::id() == parent_id {
+                std::thread::sleep(Duration::from_millis(500));
+            } else {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            let data = resource
+                .try_access(|data| *data)
+                .expect("failed to access data");
+
+            drop(resource);
+
+            if std::process::id() == parent_id {
+                assert!(matches!(read, Err(Error::WouldBlock { .. })));
+                assert!(matches!(write, Err(Error::WouldBlock { .. })));
+                assert!(waited < Duration::from_millis(5), "waited {:?}", waited);
+            }
+            assert_eq!(data, 1000);
+        }
+
+        #[test]
+        fn test_many_proc_try_access_readers() {
+            use std::time::{Duration, Instant};
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(3);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 0).expect("failed to open resource");
+
+            // without a writer, trying to read succeeds however busy the other readers are.
+            // Opening, closing and counting take the write lock, so the parent only tries
+            // while every child is attached and reading, and tells them when it is done.
+            const DONE: usize = 1000;
+            let wait_for = |check: &dyn Fn(usize) -> bool| {
+                let start = Instant::now();
+                while !resource.access(|data| check(*data)).expect("failed to access data") {
+                    assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            };
+            let mut blocked = 0;
+            if std::process::id() == parent_id {
+                wait_for(&|data| data == 3);
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(200) {
+                    if resource.try_access(|data| *data).is_err() {
+                        blocked += 1;
+                    }
+                }
+                resource
+                    .access_mut(|data| *data += DONE)
+                    .expect("failed to access mutable data");
+                // stay attached until the children are done
+                wait_for(&|data| data == DONE);
+                std::thread::sleep(Duration::from_millis(100));
+            } else {
+                resource
+                    .access_mut(|data| *data += 1)
+                    .expect("failed to access mutable data");
+                while resource.access(|data| *data).expect("failed to access data") < DONE {}
+                resource
+                    .access_mut(|data| *data -= 1)
+                    .expect("failed to access mutable data");
+            }
+
+            drop(resource);
+
+            assert_eq!(blocked, 0, "blocked {} times", blocked);
+        }
+
+        #[test]
+        fn test_many_proc_owner_died() {
+            use crate::error::Error;
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // the child is killed while holding the write lock
+            if std::process::id() != parent_id {
+                std::thread::sleep(Duration::from_millis(50));
+                let _ = resource.access_mut(|data| {
+                    *data = 100;
+                    unsafe {
+                        libc::kill(libc::getpid(), libc::SIGKILL);
+                    }
+                });
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+
+            // opening, closing and reading leave the report to writers
+            let other =
+                UnixSharedResource::<usize>::new(&name, 0).expect("failed to open resource");
+            drop(other);
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data after recovery");
+
+            // writers are turned away until the value is marked consistent
+            let died = resource.access_mut(|data| *data);
+            let still_died = resource.access_mut(|data| *data);
+            let mut unchecked = resource.write().expect("failed to lock resource");
+            *unchecked = 0;
+            let refused = unchecked.commit();
+            let mut guard = resource.write().expect("failed to lock resource");
+            let owner = guard.owner_died();
+            let checked = *guard;
+            guard.mark_consistent();
+            guard.commit().expect("failed to commit value");
+            let written = resource.access_mut(|data| { *data += 1; *data });
+
+            drop(resource);
+
+            assert_eq!(data, 1000);
+            assert!(matches!(died, Err(Error::OwnerDied { pid, .. }) if pid != parent_id));
+            assert!(matches!(still_died, Err(Error::OwnerDied { .. })));
+            assert!(matches!(refused, Err(Error::OwnerDied { .. })));
+            assert!(matches!(owner, Some(pid) if pid != parent_id));
+            assert_eq!(checked, 1000);
+            assert!(matches!(written, Ok(1001)));
+        }
+
+        #[test]
+        fn test_single_proc_reused_pid_owner() {
+            use crate::options::LockTimeout;
+            use crate::unix::header::Member;
+            use crate::unix::process::start_time;
+            use std::sync::atomic::Ordering;
+            use std::time::Duration;
+
+            let name = init();
+
+            let pid = std::process::id();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let other =
+                UnixSharedResource::<usize>::new(&name, 0).expect("failed to open resource");
+
+            // the lock is left to a process that died, whose PID now belongs to this one
+            std::mem::forget(resource.write().expect("failed to lock resource"));
+            let owners = &resource.resource.header().owners;
+            let stale = Member::pack(pid, start_time(pid).expect("failed to read start time") + 1);
+            owners.writer.store(stale, Ordering::Release);
+            owners.turnstile.store(stale, Ordering::Release);
+
+            let recovered = other
+                .write_timeout(LockTimeout::After(Duration::from_secs(1)))
+                .map(|mut guard| {
+                    let owner = guard.owner_died();
+                    guard.mark_consistent();
+                    guard.abort().expect("failed to unlock resource");
+                    owner
+                });
+
+            drop(other);
+            drop(resource);
+
+            assert!(matches!(recovered, Ok(Some(owner)) if owner == pid));
+        }
+
+        #[test]
+        fn test_reject_incompatible_segment() {
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+
+            let name = init();
+
+            write_raw_segment(&name, &[0xAB; 256]);
+
+            // a segment too small for a header is rejected, since its creator sizes it at once
+            let resource = UnixSharedResource::<usize>::open(
+                &name,
+                OpenMode::CreateOrOpen(1000),
+                &ResourceOptions::default(),
+            );
+
+            unlink_raw_segment(&name);
+
+            assert!(matches!(resource, Err(Error::IncompatibleSegment { .. })));
+        }
+
+        #[test]
+        fn test_reject_overflowing_capacity() {
+            use crate::error::Error;
+            use std::sync::atomic::Ordering;
+
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // a garbage capacity is rejected rather than overflowing the segment length
+            let capacity = &resource.resource.header().capacity;
+            let previous = capacity.swap(u64::MAX / 2 + 1, Ordering::AcqRel);
+            let other = UnixSharedResource::<usize>::new(&name, 1000);
+            capacity.store(previous, Ordering::Release);
+
+            drop(resource);
+
+            assert!(matches!(other, Err(Error::IncompatibleSegment { .. })));
+        }
+
+        #[test]
+        fn test_many_proc_robust_mutate() {
+            use crate::options::LockKind;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource = UnixSharedResource::<usize>::with_lock(&name, 1000, LockKind::RobustMutex)
+                .expect("failed to open resource");
+
+            let val: usize = if std::process::id() == parent_id {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                resource
+                    .access_mut(|data| *data)
+                    .expect("failed to access data")
+            } else {
+                let val = resource
+                    .access_mut(|data| { *data = 100; *data })
+                    .expect("failed to access mutable data");
+                // stay attached until the parent read the value
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                val
+            };
+
+            drop(resource);
+
+            assert_eq!(val, 100);
+        }
+
+        #[test]
+        fn test_many_proc_robust_owner_died() {
+            use crate::error::Error;
+            use crate::options::LockKind;
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource = UnixSharedResource::<usize>::with_lock(&name, 1000, LockKind::RobustMutex)
+                .expect("failed to open resource");
+
+            // the child is killed while holding the lock
+            if std::process::id() != parent_id {
+                std::thread::sleep(Duration::from_millis(50));
+                let _ = resource.access_mut(|data| {
+                    *data = 100;
+                    unsafe {
+                        libc::kill(libc::getpid(), libc::SIGKILL);
+                    }
+                });
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data after recovery");
+            let died = resource.access_mut(|data| *data);
+            let owner = resource.owner_died();
+            resource.mark_consistent().expect("failed to mark value consistent");
+            let written = resource.access_mut(|data| *data);
+
+            drop(resource);
+
+            assert_eq!(data, 1000);
+            assert!(matches!(died, Err(Error::OwnerDied { pid, .. }) if pid != parent_id));
+            assert!(matches!(owner, Some(pid) if pid != parent_id));
+            assert!(matches!(written, Ok(1000)));
+        }
+
+        #[test]
+        fn test_many_proc_killed_member() {
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // the child is killed while attached, outside of the lock
+            if std::process::id() != parent_id {
+                resource
+                    .access_mut(|data| { *data = 100; })
+                    .expect("failed to access mutable data");
+                unsafe {
+                    libc::kill(libc::getpid(), libc::SIGKILL);
+                }
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+            drop(resource);
+
+            // the parent was the final live process, so the next run starts afresh
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+
+            drop(resource);
+
+            assert_eq!(data, 1000);
+        }
+
+        #[test]
+        fn test_single_proc_close() {
+            use crate::outcome::CloseOutcome;
+
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            let outcome = resource.close().expect("failed to close resource");
+
+            assert_eq!(
+                outcome,
+                CloseOutcome { was_final: true, unlinked_memory: true, unlinked_lock: true }
+            );
+        }
+
+        #[test]
+        fn test_many_proc_close() {
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // the child closes first
+            if std::process::id() == parent_id {
+                std::thread::sleep(Duration::from_millis(150));
+            } else {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            let outcome = resource.close().expect("failed to close resource");
+
+            assert_eq!(outcome.was_final, std::process::id() == parent_id);
+            assert_eq!(outcome.unlinked_memory, std::process::id() == parent_id);
+        }
+
+        #[test]
+        fn test_many_proc_destroy() {
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // the parent destroys the resource while the child is attached
+            if std::process::id() == parent_id {
+                std::thread::sleep(Duration::from_millis(50));
+                let outcome = resource.destroy().expect("failed to destroy resource");
+                assert!(!outcome.was_final);
+                assert!(outcome.unlinked_memory);
+                return;
+            }
+
+            resource
+                .access_mut(|data| { *data = 100; })
+                .expect("failed to access mutable data");
+            std::thread::sleep(Duration::from_millis(150));
+
+            // the child still uses the destroyed resource, while a new one starts afresh
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+            let other =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let other_data = other
+                .access(|data| *data)
+                .expect("failed to access data");
+
+            let outcome = resource.close().expect("failed to close resource");
+            drop(other);
+
+            assert_eq!(data, 100);
+            assert_eq!(other_data, 1000);
+            assert!(!outcome.unlinked_memory);
+        }
+
+        #[test]
+        fn test_single_proc_open_modes() {
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+
+            let name = init();
+
+            let missing =
+                UnixSharedResource::<usize>::open(&name, OpenMode::OpenExisting, &ResourceOptions::default());
+            assert!(matches!(missing, Err(Error::NotFound { .. })));
+
+            let resource =
+                UnixSharedResource::<usize>::open(&name, OpenMode::CreateNew(1000), &ResourceOptions::default())
+                    .expect("failed to create resource");
+            let duplicate =
+                UnixSharedResource::<usize>::open(&name, OpenMode::CreateNew(100), &ResourceOptions::default());
+            let existing =
+                UnixSharedResource::<usize>::open(&name, OpenMode::OpenExisting, &ResourceOptions::default())
+                    .expect("failed to open resource");
+            let data = existing
+                .access(|data| *data)
+                .expect("failed to access data");
+
+            assert!(resource.created());
+            assert!(!existing.created());
+            assert!(matches!(duplicate, Err(Error::AlreadyExists { .. })));
+            assert_eq!(data, 1000);
+
+            drop(existing);
+            drop(resource);
+        }
+
+        #[test]
+        fn test_single_proc_options() {
+            use crate::options::{CleanupPolicy, OpenMode, ResourceOptions};
+            use std::os::unix::fs::PermissionsExt;
+            use std::sync::atomic::Ordering;
+
+            let name = init();
+
+            let options = ResourceOptions {
+                permissions: 0o600,
+                initial_capacity: 4096,
+                cleanup: CleanupPolicy::Keep,
+                ..ResourceOptions::default()
+            };
+
+            let resource =
+                UnixSharedResource::<usize>::open(&name, OpenMode::CreateNew(1000), &options)
+                    .expect("failed to create resource");
+            let capacity = resource.resource.header().capacity.load(Ordering::Acquire);
+            let permissions = std::fs::metadata(format!("/dev/shm/shm_{}", name))
+                .expect("failed to stat shared memory")
+                .permissions()
+                .mode();
+            resource
+                .access_mut(|data| { *data = 100; })
+                .expect("failed to access mutable data");
+            let outcome = resource.close().expect("failed to close resource");
+
+            // the value outlives the last process
+            let resource =
+                UnixSharedResource::<usize>::open(&name, OpenMode::OpenExisting, &options)
+                    .expect("failed to open resource");
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+            let destroyed = resource.destroy().expect("failed to destroy resource");
+
+            assert_eq!(capacity, 4096);
+            assert_eq!(permissions & 0o777, 0o600);
+            assert!(outcome.was_final);
+            assert!(!outcome.unlinked_memory);
+            assert_eq!(data, 100);
+            assert!(destroyed.unlinked_memory);
+        }
+
+        #[test]
+        fn test_single_proc_builder() {
+            use crate::error::Error;
+            use crate::options::LockKind;
+            use crate::SharedResource;
+
+            let name = init();
+
+            // by default, the builder only opens an existing resource
+            let missing = SharedResource::<usize>::builder(&name).build();
+
+            let resource = SharedResource::<usize>::builder(&name)
+                .create_or_open(1000)
+                .lock(LockKind::RobustMutex)
+                .build()
+                .expect("failed to create resource");
+            let other = SharedResource::<usize>::builder(&name)
+                .lock(LockKind::RobustMutex)
+                .create_or_open(0)
+                .build()
+                .expect("failed to open resource");
+            let existing = SharedResource::<usize>::builder(&name)
+                .create_new(0)
+                .lock(LockKind::RobustMutex)
+                .build();
+            let mismatched = SharedResource::<usize>::builder(&name).build();
+            let data = other.access(|data| *data).expect("failed to access data");
+            let created = (resource.created(), other.created());
+
+            other.close().expect("failed to close resource");
+            let outcome = resource.close().expect("failed to close resource");
+
+            assert!(matches!(missing, Err(Error::NotFound { .. })));
+            assert!(matches!(existing, Err(Error::AlreadyExists { .. })));
+            assert!(matches!(mismatched, Err(Error::IncompatibleSegment { .. })));
+            assert_eq!(data, 1000);
+            assert_eq!(created, (true, false));
+            assert!(outcome.was_final);
+        }
+
+        #[test]
+        fn test_many_proc_created() {
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let created = resource.created();
+
+            // only one of the processes created the resource, so the other writes to it
+            if !created {
+                resource
+                    .access_mut(|data| { *data += 1; })
+                    .expect("failed to access mutable data");
+            }
+            std::thread::sleep(Duration::from_millis(100));
+
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+
+            if std::process::id() != parent_id {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            drop(resource);
+
+            assert_eq!(data, 1001);
+        }
+
+        #[test]
+        fn test_single_proc_guards() {
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            // dropping the guard writes the value back
+            {
+                let mut data = resource.write().expect("failed to lock resource");
+                *data += 1;
+            }
+            let mut data = resource.write().expect("failed to lock resource");
+            *data += 1;
+            data.commit().expect("failed to commit resource");
+            let mut data = resource.write().expect("failed to lock resource");
+            *data = 0;
+            data.abort().expect("failed to abort resource");
+
+            let data = resource.read().expect("failed to lock resource");
+            let other = resource.read().expect("failed to lock resource");
+            let value = *data;
+            drop(other);
+            drop(data);
+            drop(resource);
+
+            assert_eq!(value, 1002);
+        }
+
+        #[test]
+        fn test_single_proc_accessors() {
+            use crate::error::Error;
+
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<Vec<usize>>::new(&name, vec![]).expect("failed to open resource");
+
+            // the clojures take ownership of, and mutate, captured state
+            let values = vec![1, 2, 3];
+            resource
+                .access_mut(move |data| { data.extend(values); })
+                .expect("failed to access mutable data");
+            let mut count = 0;
+            resource
+                .access(|data| { count += data.len(); })
+                .expect("failed to access data");
+
+            // a failed clojure leaves the value as it was
+            let failed = resource.access_mut_fallible(|data| {
+                data.push(4);
+                if data.len() > 3 {
+                    return Err(Error::UnsupportedOS);
+                }
+                Ok(())
+            });
+            let len = resource
+                .access_mut_fallible(|data| Ok::<usize, Error>(data.len()))
+                .expect("failed to access mutable data");
+
+            drop(resource);
+
+            assert_eq!(count, 3);
+            assert!(matches!(failed, Err(Error::UnsupportedOS)));
+            assert_eq!(len, 3);
+        }
+
+        #[test]
+        fn test_single_proc_panic() {
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+            use std::panic::{catch_unwind, AssertUnwindSafe};
+
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let other =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+
+            let panicked = catch_unwind(AssertUnwindSafe(|| {
+                resource.access_mut(|data| {
+                    *data = 0;
+                    panic!("accessor panicked");
+                })
+            }));
+
+            // the lock was released, but the value cannot be trusted until cleared
+            let poisoned = other.access(|data| *data);
+            let is_poisoned = other.is_poisoned();
+            other.clear_poison().expect("failed to clear poison");
+            let data = other.access(|data| *data).expect("failed to access data");
+
+            drop(other);
+            drop(resource);
+
+            // without poisoning, the value is only left as it was
+            let options = ResourceOptions {
+                poison_on_panic: false,
+                ..ResourceOptions::default()
+            };
+            let resource =
+                UnixSharedResource::<usize>::open(&name, OpenMode::CreateOrOpen(1000), &options)
+                    .expect("failed to open resource");
+            let _ = catch_unwind(AssertUnwindSafe(|| {
+                let mut data = resource.write().expect("failed to lock resource");
+                *data = 0;
+                panic!("guard panicked");
+            }));
+            let unpoisoned = resource.access(|data| *data).expect("failed to access data");
+
+            drop(resource);
+
+            assert!(panicked.is_err());
+            assert!(matches!(poisoned, Err(Error::Poisoned { .. })));
+            assert!(is_poisoned);
+            assert_eq!(data, 1000);
+            assert_eq!(unpoisoned, 1000);
+        }
+
+        #[test]
+        fn test_single_proc_unchanged() {
+            use std::sync::atomic::Ordering;
+
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let version = || resource.resource.header().version.load(Ordering::Acquire);
+
+            let initial = version();
+            resource
+                .access_mut(|data| *data)
+                .expect("failed to access mutable data");
+            resource
+                .access_mut(|data| { *data = 1000; })
+                .expect("failed to access mutable data");
+            let unchanged = version();
+            resource
+                .access_mut(|data| { *data = 100; })
+                .expect("failed to access mutable data");
+            let changed = version();
+
+            drop(resource);
+
+            assert_eq!(unchanged, initial);
+            assert_eq!(changed, initial + 1);
+        }
+
+        #[test]
+        fn test_single_proc_cache() {
+            let name = init();
+
+            let resource =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let other =
+                UnixSharedResource::<usize>::new(&name, 1000).expect("failed to open resource");
+            let cached_version = |resource: &UnixSharedResource<usize>| {
+                resource.cache.borrow().as_ref().map(|cached| cached.version)
+            };
+
+            let initial = resource.access(|data| *data).expect("failed to access data");
+            let initial_version = cached_version(&resource);
+
+            // a write by another handle makes the cached copy outdated
+            other
+                .access_mut(|data| { *data = 100; })
+                .expect("failed to access mutable data");
+            let other_version = cached_version(&other);
+            let data = resource.access(|data| *data).expect("failed to access data");
+            let guard = resource.read().expect("failed to lock resource");
+            let nested = resource.read().expect("failed to lock resource");
+            let guarded = (*guard, *nested);
+            drop(nested);
+            drop(guard);
+
+            drop(other);
+            drop(resource);
+
+            assert_eq!(initial, 1000);
+            assert_eq!(initial_version, Some(0));
+            assert_eq!(other_version, Some(1));
+            assert_eq!(data, 100);
+            assert_eq!(guarded, (100, 100));
+        }
+
+        #[test]
+        fn test_single_proc_pod() {
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+            use bytemuck::{Pod, Zeroable};
+            use std::sync::atomic::Ordering;
+
+            #[repr(C)]
+            #[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+            struct Point {
+                x: u64,
+                y: u64,
+            }
+
+            let name = init();
+
+            let options = ResourceOptions::default();
+            let timeout = options.timeout;
+            let resource = UnixSharedResource::<Point>::open_pod(
+                &name,
+                OpenMode::CreateOrOpen(|| Point { x: 1, y: 2 }),
+                &options,
+            )
+            .expect("failed to create resource");
+            let other = UnixSharedResource::<Point>::open_pod(
+                &name,
+                OpenMode::<fn() -> Point>::OpenExisting,
+                &options,
+            )
+            .expect("failed to open resource");
+            let mismatched = UnixSharedResource::<u32>::open_pod(
+                &name,
+                OpenMode::<fn() -> u32>::OpenExisting,
+                &options,
+            );
+
+            // the value is written and read in place
+            resource
+                .access_pod_mut_timeout(timeout, |point| { point.x = 10; })
+                .expect("failed to access mutable data");
+            let address = resource
+                .access_pod_timeout(timeout, |point| point as *const Point as usize)
+                .expect("failed to access data");
+            let data = other
+                .access_pod_timeout(timeout, |point| *point)
+                .expect("failed to access data");
+            let version = other.resource.header().version.load(Ordering::Acquire);
+
+            drop(other);
+            drop(resource);
+
+            assert!(matches!(mismatched, Err(Error::TypeMismatch { .. })));
+            assert_eq!(data, Point { x: 10, y: 2 });
+            assert_eq!(address % 4096, 0);
+            assert_eq!(version, 1);
+        }
+
+        #[test]
+        fn test_many_proc_pod_mutate() {
+            use crate::options::{OpenMode, ResourceOptions};
+
+            let name = init();
+
+            spawn_children(3);
+
+            let options = ResourceOptions::default();
+            let resource = UnixSharedResource::<u64>::open_pod(
+                &name,
+                OpenMode::CreateOrOpen(|| 0),
+                &options,
+            )
+            .expect("failed to open resource");
+
+            for _ in 0..100 {
+                resource
+                    .access_pod_mut_timeout(options.timeout, |data| { *data += 1; })
+                    .expect("failed to access mutable data");
+            }
+            std::thread::sleep(std::time::Duration::from_millis(200));
+
+            let data = resource
+                .access_pod_timeout(options.timeout, |data| *data)
+                .expect("failed to access data");
+
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            drop(resource);
+
+            assert_eq!(data, 400);
+        }
+
+        #[test]
+        #[cfg(feature = "rkyv")]
+        fn test_many_proc_archive() {
+            use crate::options::{OpenMode, ResourceOptions};
+            use crate::unix::archive::UnixSharedArchive;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let options = ResourceOptions::default();
+            let resource = UnixSharedArchive::<Vec<u64>>::open(
+                &name,
+                OpenMode::CreateOrOpen(|| vec![1, 2, 3]),
+                &options,
+            )
+            .expect("failed to open resource");
+
+            let sum: u64 = if std::process::id() == parent_id {
+                // the archive is checked on the first read, and again once the child changed it
+                resource
+                    .access_timeout(options.timeout, |data| data.len())
+                    .expect("failed to access data");
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                resource
+                    .access_timeout(options.timeout, |data| {
+                        data.iter().map(|v| v.to_native()).sum()
+                    })
+                    .expect("failed to access data")
+            } else {
+                resource
+                    .access_mut_timeout(options.timeout, |data| { data.push(100); })
+                    .expect("failed to access mutable data");
+                // stay attached until the parent read the value
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                106
+            };
+
+            drop(resource);
+
+            assert_eq!(sum, 106);
+        }
+
+        #[test]
+        fn test_single_proc_open_or_init() {
+            use crate::options::{OpenMode, ResourceOptions};
+
+            let name = init();
+
+            let resource = UnixSharedResource::<usize>::open_with(
+                &name,
+                OpenMode::CreateOrOpen(|| 1000),
+                &ResourceOptions::default(),
+                None,
+            )
+            .expect("failed to create resource");
+            let existing = UnixSharedResource::<usize>::open_with(
+                &name,
+                OpenMode::CreateOrOpen(|| -> usize { panic!("initializer ran for an existing resource") }),
+                &ResourceOptions::default(),
+                None,
+            )
+            .expect("failed to open resource");
+            let data = existing
+                .access(|data| *data)
+                .expect("failed to access data");
+
+            drop(existing);
+            drop(resource);
+
+            assert_eq!(data, 1000);
+        }
+
+        #[test]
+        fn test_many_proc_open_or_init() {
+            use crate::options::{OpenMode, ResourceOptions};
+            use std::cell::Cell;
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            // the process opening the resource waits for the slow initializer of the creator
+            let ran = Cell::new(false);
+            let resource = UnixSharedResource::<usize>::open_with(
+                &name,
+                OpenMode::CreateOrOpen(|| {
+                    ran.set(true);
+                    std::thread::sleep(Duration::from_millis(200));
+                    1000
+                }),
+                &ResourceOptions::default(),
+                None,
+            )
+            .expect("failed to open resource");
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+
+            if std::process::id() != parent_id {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            let created = resource.created();
+            drop(resource);
+
+            assert_eq!(data, 1000);
+            assert_eq!(ran.get(), created);
+        }
+
+        #[test]
+        fn test_many_proc_slow_initializer() {
+            use crate::error::Error;
+            use crate::options::{LockTimeout, OpenMode, ResourceOptions};
+            use std::time::{Duration, Instant};
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            // the initializer takes longer than the lock timeout, which openers do not wait
+            // under
+            let options = ResourceOptions {
+                timeout: LockTimeout::After(Duration::from_millis(100)),
+                ..ResourceOptions::default()
+            };
+            if std::process::id() != parent_id {
+                let resource = UnixSharedResource::<usize>::open_with(
+                    &name,
+                    OpenMode::CreateNew(|| {
+                        std::thread::sleep(Duration::from_millis(500));
+                        1000
+                    }),
+                    &options,
+                    None,
+                )
+                .expect("failed to create resource");
+
+                std::thread::sleep(Duration::from_millis(100));
+                drop(resource);
+                return;
+            }
+
+            let start = Instant::now();
+            let open = |options: &ResourceOptions| loop {
+                match UnixSharedResource::<usize>::open(&name, OpenMode::OpenExisting, options) {
+                    Err(Error::NotFound { .. }) if start.elapsed() < Duration::from_secs(5) => {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    res => return res,
+                }
+            };
+
+            let impatient = open(&ResourceOptions {
+                init_timeout: LockTimeout::After(Duration::from_millis(50)),
+                ..options
+            });
+            let resource = open(&options).expect("failed to open resource");
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+
+            // outlast the child
+            std::thread::sleep(Duration::from_millis(200));
+            drop(resource);
+
+            assert!(matches!(impatient, Err(Error::Initializing { .. })));
+            assert_eq!(data, 1000);
+        }
+
+        #[test]
+        fn test_single_proc_open_or_init_panic() {
+            use crate::options::{LockTimeout, OpenMode, ResourceOptions};
+            use std::time::Duration;
+
+            let name = init();
+
+            let options = ResourceOptions {
+                timeout: LockTimeout::After(Duration::from_millis(500)),
+                ..ResourceOptions::default()
+            };
+
+            // the segment of a panicking initializer is unlinked
+            let panicked = std::panic::catch_unwind(|| {
+                UnixSharedResource::<usize>::open_with(
+                    &name,
+                    OpenMode::CreateOrOpen(|| -> usize { panic!("failed to load the value") }),
+                    &options,
+                    None,
+                )
+            });
+            let is_linked = std::path::Path::new(&format!("/dev/shm/shm_{}", name)).exists();
+
+            // so is an empty segment left behind by a creator that was killed
+            write_raw_segment(&name, &[]);
+            let resource = UnixSharedResource::<usize>::open_with(
+                &name,
+                OpenMode::CreateOrOpen(|| 7),
+                &options,
+                None,
+            )
+            .expect("failed to open resource");
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+            let created = resource.created();
+
+            drop(resource);
+
+            assert!(panicked.is_err());
+            assert!(!is_linked);
+            assert_eq!(data, 7);
+            assert!(created);
+        }
+
+        #[test]
+        fn test_many_proc_killed_creator() {
+            use crate::options::{LockTimeout, OpenMode, ResourceOptions};
+            use std::time::{Duration, Instant};
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(1);
+
+            let options = ResourceOptions {
+                timeout: LockTimeout::After(Duration::from_millis(500)),
+                ..ResourceOptions::default()
+            };
+
+            // the child is killed while building the value of the resource it created
+            if std::process::id() != parent_id {
+                let _ = UnixSharedResource::<usize>::open_with(
+                    &name,
+                    OpenMode::CreateOrOpen(|| -> usize {
+                        unsafe {
+                            libc::kill(libc::getpid(), libc::SIGKILL);
+                        }
+                        0
+                    }),
+                    &options,
+                    None,
+                );
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+            let start = Instant::now();
+            let resource = UnixSharedResource::<usize>::open_with(
+                &name,
+                OpenMode::CreateOrOpen(|| 7),
+                &options,
+                None,
+            )
+            .expect("failed to open resource");
+            let elapsed = start.elapsed();
+            let data = resource
+                .access(|data| *data)
+                .expect("failed to access data");
+            let created = resource.created();
+
+            drop(resource);
+
+            assert_eq!(data, 7);
+            assert!(created);
+            assert!(elapsed < Duration::from_millis(100));
+        }
+
+        #[test]
+        fn test_single_proc_initializer() {
+            use crate::fingerprint::TypeFingerprint;
+            use crate::options::{OpenMode, ResourceOptions};
+            use crate::unix::shared_mem::{Initializer, SharedMemory};
+            use std::cell::Cell;
+
+            let name = init();
+
+            // a value built for a segment that has to be created again is reused
+            let runs = Cell::new(0);
+            let mut initializer = Initializer::new(|| {
+                runs.set(runs.get() + 1);
+                return Ok(vec![1, 2, 3]);
+            });
+            let options = ResourceOptions::default();
+            let fingerprint = TypeFingerprint::of_layout::<[u8; 3]>();
+            let memory = SharedMemory::<()>::open(
+                &name,
+                OpenMode::CreateNew(&mut initializer),
+                &options,
+                &fingerprint,
+            )
+            .expect("failed to create shared memory");
+            memory.unlink().expect("failed to unlink shared memory");
+            memory.close().expect("failed to close shared memory");
+            let memory = SharedMemory::<()>::open(
+                &name,
+                OpenMode::CreateNew(&mut initializer),
+                &options,
+                &fingerprint,
+            )
+            .expect("failed to create shared memory");
+            let data = memory
+                .with_bytes(|bytes| bytes.to_vec())
+                .expect("failed to read shared memory");
+            memory.unlink().expect("failed to unlink shared memory");
+            memory.close().expect("failed to close shared memory");
+
+            assert_eq!(runs.get(), 1);
+            assert_eq!(data, vec![1, 2, 3]);
+        }
+
+        #[test]
+        fn test_reject_mismatched_lock_kind() {
+            use crate::error::Error;
+            use crate::options::LockKind;
+
+            let name = init();
+
+            let resource = UnixSharedResource::<usize>::with_lock(&name, 1000, LockKind::RobustMutex)
+                .expect("failed to open resource");
+
+            let other = UnixSharedResource::<usize>::new(&name, 1000);
+
+            drop(resource);
+
+            assert!(matches!(other, Err(Error::IncompatibleSegment { .. })));
+        }
+
+        #[test]
+        fn test_reject_mismatched_type() {
+            use crate::error::Error;
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Serialize, Deserialize)]
+            struct Stats {
+                count: u64,
+                mean: f64,
+            }
+
+            mod other {
+                use serde::{Deserialize, Serialize};
+
+                // same shape as `Stats`, as if compiled into another binary
+                #[derive(Serialize, Deserialize)]
+                pub struct Stats {
+                    pub count: u64,
+                    pub mean: f64,
+                }
+            }
+
+            let name = init();
+
+            let resource = UnixSharedResource::<u64>::new(&name, 1000)
+                .expect("failed to open resource");
+            let mismatched = UnixSharedResource::<Stats>::new(&name, Stats { count: 0, mean: 0.0 });
+            drop(resource);
+
+            let name = init();
+
+            let resource = UnixSharedResource::<Stats>::new(&name, Stats { count: 1, mean: 2.0 })
+                .expect("failed to open resource");
+            let same_shape = UnixSharedResource::<other::Stats>::new(
+                &name,
+                other::Stats { count: 0, mean: 0.0 },
+            )
+            .expect("failed to open resource with the same shape");
+            let count = same_shape
+                .access(|data| data.count)
+                .expect("failed to access data");
+            drop(same_shape);
+            drop(resource);
+
+            assert!(matches!(
+                mismatched,
+                Err(Error::TypeMismatch { ref expected, ref found })
+                    if expected.ends_with("Stats") && found == "u64"
+            ));
+            assert_eq!(count, 1);
+        }
+
+        #[test]
+        fn test_single_proc_migrate() {
+            use crate::codec::{Bincode, Codec};
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Serialize, Deserialize)]
+            struct ConfigV0 {
+                retries: u32,
+            }
+
+            #[derive(Serialize, Deserialize, Debug, PartialEq)]
+            struct ConfigV1 {
+                retries: u32,
+                endpoint: String,
+            }
+
+            let name = init();
+
+            let old = UnixSharedResource::<ConfigV0>::new(&name, ConfigV0 { retries: 3 })
+                .expect("failed to open resource");
+
+            // without a migration, a newer process cannot use the value
+            let options = ResourceOptions {
+                schema_version: 1,
+                ..ResourceOptions::default()
+            };
+            let unmigrated =
+                UnixSharedResource::<ConfigV1>::open(&name, OpenMode::OpenExisting, &options);
+
+            let migrated = UnixSharedResource::<ConfigV1>::open_with(
+                &name,
+                OpenMode::<fn() -> ConfigV1>::OpenExisting,
+                &options,
+                Some(Box::new(|from, bytes| {
+                    assert_eq!(from, 0);
+                    let codec: &dyn Codec = &Bincode;
+                    let old = codec.decode::<ConfigV0>(bytes)?;
+                    return Ok(ConfigV1 {
+                        retries: old.retries,
+                        endpoint: "localhost".to_string(),
+                    });
+                })),
+            )
+            .expect("failed to migrate resource");
+            let other = UnixSharedResource::<ConfigV1>::open(&name, OpenMode::OpenExisting, &options)
+                .expect("failed to open migrated resource");
+            let data = other
+                .access(|data| data.endpoint.clone())
+                .expect("failed to access data");
+
+            // the older process can neither read nor overwrite the migrated value
+            let old_read = old.access(|data| data.retries);
+            let old_write = old.access_mut(|data| { data.retries = 0; });
+            let old_open = UnixSharedResource::<ConfigV0>::new(&name, ConfigV0 { retries: 3 });
+            let retries = migrated
+                .access(|data| data.retries)
+                .expect("failed to access data");
+
+            drop(other);
+            drop(migrated);
+            drop(old);
+
+            assert!(matches!(
+                unmigrated,
+                Err(Error::SchemaMismatch { expected: 1, found: 0, .. })
+            ));
+            assert_eq!(data, "localhost");
+            assert_eq!(retries, 3);
+            assert!(matches!(old_read, Err(Error::SchemaMismatch { expected: 0, found: 1, .. })));
+            assert!(matches!(old_write, Err(Error::SchemaMismatch { .. })));
+            assert!(matches!(old_open, Err(Error::SchemaMismatch { .. })));
+        }
+
+        #[test]
+        fn test_single_proc_corrupted() {
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+            use std::sync::atomic::Ordering;
+
+            let name = init();
+
+            let resource = UnixSharedResource::<Vec<u32>>::new(&name, vec![1, 2, 3])
+                .expect("failed to open resource");
+            let other = UnixSharedResource::<Vec<u32>>::new(&name, vec![])
+                .expect("failed to open resource");
+            other
+                .access(|data| data.len())
+                .expect("failed to access data");
+
+            // a writer dying halfway through leaves bytes that do not match the checksum
+            unsafe {
+                let data = resource.resource.data_ptr().expect("failed to map data");
+                *data.add(8) ^= 0xff;
+            }
+            resource.resource.header().version.fetch_add(1, Ordering::AcqRel);
+            let corrupted = other.access(|data| data.len());
+
+            drop(other);
+            drop(resource);
+
+            let pod_name = init();
+
+            let pod = UnixSharedResource::<u64>::open_pod(
+                &pod_name,
+                OpenMode::CreateNew(|| 1000),
+                &ResourceOptions::default(),
+            )
+            .expect("failed to create resource");
+            let timeout = pod.timeout();
+            pod.access_pod_mut_timeout(timeout, |data| { *data += 1; })
+                .expect("failed to access mutable data");
+            let data = pod
+                .access_pod_timeout(timeout, |data| *data)
+                .expect("failed to access data");
+            unsafe {
+                *pod.resource.data_ptr().expect("failed to map data") ^= 0xff;
+            }
+            let pod_corrupted = pod.access_pod_timeout(timeout, |data| *data);
+
+            drop(pod);
+
+            assert!(matches!(
+                corrupted,
+                Err(Error::Corrupted { name: ref corrupted_name }) if corrupted_name.ends_with(&name)
+            ));
+            assert_eq!(data, 1001);
+            assert!(matches!(pod_corrupted, Err(Error::Corrupted { .. })));
+        }
+
+        #[test]
+        fn test_many_proc_killed_writer() {
+            use crate::error::Error;
+            use crate::options::{LockKind, OpenMode, ResourceOptions};
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            let options = ResourceOptions {
+                lock: LockKind::RobustMutex,
+                ..ResourceOptions::default()
+            };
+            let resource = UnixSharedResource::<[u64; 2]>::open_pod(
+                &name,
+                OpenMode::CreateOrOpen(|| [1, 1]),
+                &options,
+            )
+            .expect("failed to open resource");
+            let timeout = resource.timeout();
+
+            spawn_children(1);
+
+            // the child is killed halfway through writing the value
+            if std::process::id() != parent_id {
+                let _ = resource.access_pod_mut_timeout(timeout, |data| {
+                    data[0] = 2;
+                    unsafe {
+                        libc::kill(libc::getpid(), libc::SIGKILL);
+                    }
+                });
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+            let data = resource
+                .access_pod_timeout(timeout, |data| *data)
+                .expect("failed to access data after recovery");
+            let died = resource.access_pod_mut_timeout(timeout, |data| data[1] = 3);
+            resource.mark_consistent().expect("failed to mark value consistent");
+            let written = resource.access_pod_mut_timeout(timeout, |data| { data[1] = 3; *data });
+
+            drop(resource);
+
+            assert_eq!(data, [1, 1]);
+            assert!(matches!(died, Err(Error::OwnerDied { .. })));
+            assert!(matches!(written, Ok([1, 3])));
+        }
+
+        #[test]
+        fn test_single_proc_double_buffer() {
+            use std::sync::atomic::Ordering;
+
+            let name = init();
+
+            let resource = UnixSharedResource::<Vec<u8>>::new(&name, vec![1; 16])
+                .expect("failed to open resource");
+            let other = UnixSharedResource::<Vec<u8>>::new(&name, vec![])
+                .expect("failed to open resource");
+            resource
+                .access_mut(|data| { data.fill(2); })
+                .expect("failed to access mutable data");
+            let active = resource.resource.header().active_index();
+
+            // a writer dying before it switches slots leaves the current value untouched
+            unsafe {
+                let header = resource.resource.header();
+                let capacity = header.capacity.load(Ordering::Acquire) as usize;
+                let current = resource.resource.data_ptr().expect("failed to map value");
+                std::ptr::write_bytes(current.sub(capacity), 0xff, 4096);
+            }
+            let data = other.access(|data| data.clone()).expect("failed to access data");
+
+            // growing moves the current value along with the second slot
+            resource
+                .access_mut(|data| { data.resize(10_000, 3); })
+                .expect("failed to access mutable data");
+            let grown = other.access(|data| data.clone()).expect("failed to access data");
+
+            drop(other);
+            drop(resource);
+
+            assert_eq!(active, 1);
+            assert_eq!(data, vec![2; 16]);
+            assert_eq!(grown.len(), 10_000);
+            assert_eq!(&grown[..16], &[2; 16]);
+            assert!(grown[16..].iter().all(|byte| *byte == 3));
+        }
+
+        #[test]
+        fn test_single_proc_codecs() {
+            use crate::codec::*;
+            use crate::options::{OpenMode, ResourceOptions};
+
+            let codecs: [&'static dyn Codec; _] = [
+                &Bincode,
+                #[cfg(feature = "postcard")]
+                &Postcard,
+                #[cfg(feature = "msgpack")]
+                &MessagePack,
+                #[cfg(feature = "cbor")]
+                &Cbor,
+                #[cfg(feature = "json")]
+                &Json,
+            ];
+
+            for codec in codecs {
+                let name = init();
+                let options = ResourceOptions {
+                    codec,
+                    ..ResourceOptions::default()
+                };
+
+                let resource = UnixSharedResource::<Vec<String>>::open(
+                    &name,
+                    OpenMode::CreateNew(vec!["a".to_string()]),
+                    &options,
+                )
+                .expect("failed to create resource");
+                resource
+                    .access_mut(|data| data.push("b".to_string()))
+                    .expect("failed to access mutable data");
+
+                let other = UnixSharedResource::<Vec<String>>::open(
+                    &name,
+                    OpenMode::OpenExisting,
+                    &options,
+                )
+                .expect("failed to open resource");
+                let data = other
+                    .access(|data| data.clone())
+                    .expect("failed to access data");
+
+                drop(other);
+                drop(resource);
+
+                assert_eq!(data, vec!["a".to_string(), "b".to_string()], "{}", codec.name());
+            }
+        }
+
+        #[test]
+        #[cfg(feature = "json")]
+        fn test_reject_mismatched_codec() {
+            use crate::codec::Json;
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+
+            let name = init();
+
+            let options = ResourceOptions {
+                codec: &Json,
+                ..ResourceOptions::default()
+            };
+            let resource =
+                UnixSharedResource::<usize>::open(&name, OpenMode::CreateNew(1000), &options)
+                    .expect("failed to create resource");
+
+            let other = UnixSharedResource::<usize>::new(&name, 1000);
+
+            drop(resource);
+
+            assert!(matches!(
+                other,
+                Err(Error::CodecMismatch { ref expected, ref found, .. })
+                    if expected == "bincode" && found == "json"
+            ));
+        }
+
+        #[test]
+        fn test_single_proc_custom_codec() {
+            use crate::codec::{Bincode, Codec, DecodeVisitor};
+            use crate::error::Error;
+            use crate::options::{OpenMode, ResourceOptions};
+
+            /// bincode, stored back to front
+            #[derive(Debug)]
+            struct Reversed;
+
+            impl Codec for Reversed {
+                fn id(&self) -> u32 {
+                    return 256;
+                }
+
+                fn name(&self) -> &str {
+                    return "reversed";
+                }
+
+                fn encode_erased(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+                    let mut bytes = Bincode.encode_erased(value)?;
+                    bytes.reverse();
+                    return Ok(bytes);
+                }
+
+                fn decode_erased(&self, bytes: &[u8], visit: &mut DecodeVisitor<'_>) -> Result<(), Error> {
+                    let bytes: Vec<u8> = bytes.iter().rev().copied().collect();
+                    return Bincode.decode_erased(&bytes, visit);
+                }
+            }
+
+            let name = init();
+
+            let options = ResourceOptions {
+                codec: &Reversed,
+                ..ResourceOptions::default()
+            };
+            let resource = UnixSharedResource::<Vec<u32>>::open(
+                &name,
+                OpenMode::CreateNew(vec![1, 2]),
+                &options,
+            )
+            .expect("failed to create resource");
+            resource
+                .access_mut(|data| data.push(3))
+                .expect("failed to access mutable data");
+
+            let other = UnixSharedResource::<Vec<u32>>::open(&name, OpenMode::OpenExisting, &options)
+                .expect("failed to open resource");
+            let data = other.access(|data| data.clone()).expect("failed to access data");
+            let bincode = UnixSharedResource::<Vec<u32>>::new(&name, Vec::new());
+
+            drop(other);
+            drop(resource);
+
+            assert_eq!(data, vec![1, 2, 3]);
+            assert!(matches!(
+                bincode,
+                Err(Error::CodecMismatch { ref expected, ref found, .. })
+                    if expected == "bincode" && found == "unknown codec 256"
+            ));
+        }
+
+        #[test]
+        fn test_single_proc_seqlock() {
+            use crate::options::{LockTimeout, OpenMode, ReadMode, ResourceOptions};
+
+            let name = init();
+
+            let resource = UnixSharedResource::<Vec<u64>>::new(&name, vec![1, 2])
+                .expect("failed to open resource");
+            let options = ResourceOptions {
+                timeout: LockTimeout::NoWait,
+                read_mode: ReadMode::SeqLock,
+                ..ResourceOptions::default()
+            };
+            let mut other =
+                UnixSharedResource::<Vec<u64>>::open(&name, OpenMode::OpenExisting, &options)
+                    .expect("failed to open resource");
+
+            // a seqlock reader does not wait for the writer holding the lock
+            let mut data = resource.write().expect("failed to lock resource");
+            data.push(3);
+            let during = other.access(|data| data.clone());
+            data.commit().expect("failed to commit resource");
+            let after = other
+                .access(|data| data.clone())
+                .expect("failed to access data");
+
+            // a reader taking the lock does
+            let held = resource.write().expect("failed to lock resource");
+            other.set_read_mode(ReadMode::Lock);
+            let locked = other.access(|data| data.clone());
+
+            drop(held);
+            drop(other);
+            drop(resource);
+
+            assert_eq!(during.expect("failed to access data"), vec![1, 2]);
+            assert_eq!(after, vec![1, 2, 3]);
+            assert!(locked.is_err());
+        }
+
+        #[test]
+        fn test_single_proc_seqlock_cached() {
+            use crate::options::{OpenMode, ReadMode, ResourceOptions};
+
+            let name = init();
+
+            let options = ResourceOptions {
+                read_mode: ReadMode::SeqLock,
+                ..ResourceOptions::default()
+            };
+            let resource = UnixSharedResource::<Vec<u64>>::open(
+                &name,
+                OpenMode::CreateNew(vec![1, 2]),
+                &options,
+            )
+            .expect("failed to create resource");
+            let other = UnixSharedResource::<Vec<u64>>::new(&name, Vec::new())
+                .expect("failed to open resource");
+            let first = resource
+                .access(|data| data.clone())
+                .expect("failed to access data");
+
+            // the cache is used as long as it holds the current version, without copying or
+            // checking the value again
+            unsafe {
+                *resource.resource.data_ptr().expect("failed to map data") ^= 0xFF;
+            }
+            let cached = resource
+                .access(|data| data.clone())
+                .expect("failed to access data");
+            unsafe {
+                *resource.resource.data_ptr().expect("failed to map data") ^= 0xFF;
+            }
+
+            // a new version is copied again
+            other
+                .access_mut(|data| data.push(3))
+                .expect("failed to access mutable data");
+            let changed = resource
+                .access(|data| data.clone())
+                .expect("failed to access data");
+
+            drop(other);
+            drop(resource);
+
+            assert_eq!(first, vec![1, 2]);
+            assert_eq!(cached, vec![1, 2]);
+            assert_eq!(changed, vec![1, 2, 3]);
+        }
+
+        #[test]
+        fn test_single_proc_seqlock_pod_corrupted() {
+            use crate::error::Error;
+            use crate::options::{OpenMode, ReadMode, ResourceOptions};
+
+            let name = init();
+
+            let options = ResourceOptions {
+                read_mode: ReadMode::SeqLock,
+                ..ResourceOptions::default()
+            };
+            let resource = UnixSharedResource::<[u64; 2]>::open_pod(
+                &name,
+                OpenMode::CreateOrOpen(|| [1, 1]),
+                &options,
+            )
+            .expect("failed to open resource");
+            let data = resource
+                .access_pod_timeout(options.timeout, |data| *data)
+                .expect("failed to access data");
+
+            // a seqlock reader checks the value it copied against its checksum
+            unsafe {
+                *resource.resource.data_ptr().expect("failed to map data") ^= 0xFF;
+            }
+            let corrupted = resource.access_pod_timeout(options.timeout, |data| *data);
+
+            drop(resource);
+
+            assert_eq!(data, [1, 1]);
+            assert!(matches!(corrupted, Err(Error::Corrupted { .. })));
+        }
+
+        #[test]
+        fn test_many_proc_killed_seqlock_writer() {
+            use crate::options::{OpenMode, ReadMode, ResourceOptions};
+            use std::sync::atomic::Ordering;
+            use std::time::Duration;
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            let options = ResourceOptions {
+                read_mode: ReadMode::SeqLock,
+                ..ResourceOptions::default()
+            };
+            let resource = UnixSharedResource::<[u64; 2]>::open_pod(
+                &name,
+                OpenMode::CreateOrOpen(|| [1, 1]),
+                &options,
+            )
+            .expect("failed to open resource");
+            let sequence = &resource.resource.header().sequence;
+
+            spawn_children(1);
+
+            // the child is killed halfway through switching slots, leaving the sequence odd
+            if std::process::id() != parent_id {
+                let _ = resource.access_pod_mut_timeout(options.timeout, |_| {
+                    sequence.fetch_add(1, Ordering::AcqRel);
+                    unsafe {
+                        libc::kill(libc::getpid(), libc::SIGKILL);
+                    }
+                });
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+            let died = sequence.load(Ordering::Acquire);
+            let locked = resource
+                .access_pod_timeout(options.timeout, |data| *data)
+                .expect("failed to access data under the lock");
+
+            // recovering the write lock makes the sequence even again
+            resource.mark_consistent().expect("failed to mark value consistent");
+            let recovered = sequence.load(Ordering::Acquire);
+            let optimistic = resource.resource.read_pod_optimistic();
+
+            drop(resource);
+
+            assert!(!died.is_multiple_of(2));
+            assert_eq!(locked, [1, 1]);
+            assert!(recovered.is_multiple_of(2));
+            assert!(matches!(optimistic, Ok(Some([1, 1]))));
+        }
+
+        #[test]
+        fn test_many_proc_seqlock() {
+            use crate::options::{OpenMode, ReadMode, ResourceOptions};
+            use std::time::{Duration, Instant};
+
+            let name = init();
+
+            let parent_id = std::process::id();
+
+            spawn_children(2);
+
+            let options = ResourceOptions {
+                read_mode: ReadMode::SeqLock,
+                ..ResourceOptions::default()
+            };
+            let resource = UnixSharedResource::<[u64; 512]>::open_pod(
+                &name,
+                OpenMode::CreateOrOpen(|| [0; 512]),
+                &options,
+            )
+            .expect("failed to open resource");
+
+            // the parent reads while the children write, and never sees half a value
+            let mut torn = 0;
+            if std::process::id() == parent_id {
+                for _ in 0..2000 {
+                    torn += resource
+                        .access_pod_timeout(options.timeout, |data| {
+                            data.iter().filter(|v| **v != data[0]).count()
+                        })
+                        .expect("failed to access data");
+                }
+            } else {
+                for _ in 0..500 {
+                    resource
+                        .access_pod_mut_timeout(options.timeout, |data| {
+                            let next = data[0] + 1;
+                            data.fill(next);
+                        })
+                        .expect("failed to access mutable data");
+                }
+            }
+
+            // wait for the children to be done writing, however busy the machine is
+            let start = Instant::now();
+            let mut data = [0; 512];
+            while data[0] < 1000 && start.elapsed() < Duration::from_secs(5) {
+                std::thread::sleep(Duration::from_millis(10));
+                data = resource
+                    .access_pod_timeout(options.timeout, |data| *data)
+                    .expect("failed to access data");
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+            drop(resource);
+
+            assert_eq!(torn, 0);
+            assert_eq!(data, [1000; 512]);
+        }
     }
 }